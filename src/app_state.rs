@@ -1,13 +1,24 @@
 use std::collections::HashMap;
+use std::fmt;
 use std::fs::File;
 use std::io::Read;
-use std::path::Path;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
 
-use anyhow::Context;
-use log::info;
-use serde::Deserialize;
+use anyhow::{bail, Context};
+use log::{info, warn};
+use rsa::RsaPrivateKey;
+use serde::{
+    de::{MapAccess, Visitor},
+    Deserialize, Deserializer,
+};
 
-use crate::DataBase;
+use crate::{
+    boinc_crypt,
+    planificator::{EligibilityRules, JsonEligibilityRules},
+    ConfigError, ConfigProblem, DataBase, PlannerChain, PlannerConfig,
+};
 
 #[derive(Clone)]
 pub struct AppState {
@@ -16,7 +27,15 @@ pub struct AppState {
     pub signing_key: String,
     pub base_url: String,
     pub database: DataBase,
-    pub weak_auth: String,
+    /// Refuse to forward scheduler replies that can’t be analysed, instead of passing them through
+    pub strict_reply_parsing: bool,
+    /// Addresses to listen on. Only read at startup.
+    pub listen: Vec<SocketAddr>,
+    /// PEM certificate chain to serve HTTPS. Only read at startup, the file is reloaded on SIGHUP.
+    pub tls_cert: Option<PathBuf>,
+    /// PEM private key matching tls_cert
+    pub tls_key: Option<PathBuf>,
+    pub planner: PlannerChain,
 }
 
 #[derive(Clone)]
@@ -25,6 +44,20 @@ pub struct Project {
     pub scheduler_url: String,
     pub url_signature: String,
     pub authenticator: String,
+    /// A disabled project is detached from every host right away
+    pub enabled: bool,
+    /// Stop fetching work, and detach once the tasks already on the host are done
+    pub detach_when_done: bool,
+    /// Keep the project attached but suspend it on every host
+    pub suspend: bool,
+    /// Which hosts can run this project
+    pub eligibility: EligibilityRules,
+    /// Science areas and other topics, matched against the preferences of the users
+    pub keywords: Vec<String>,
+}
+
+fn default_true() -> bool {
+    true
 }
 
 #[derive(Deserialize)]
@@ -32,39 +65,151 @@ struct JsonProject {
     name: String,
     scheduler_url: String,
     authenticator: String,
+    #[serde(default = "default_true")]
+    enabled: bool,
+    #[serde(default)]
+    detach_when_done: bool,
+    #[serde(default)]
+    suspend: bool,
+    #[serde(default)]
+    eligibility: JsonEligibilityRules,
+    #[serde(default)]
+    keywords: Vec<String>,
+}
+
+/// Keep the projects in order, including repeated ids, so duplicates can be reported
+fn deserialize_projects<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Vec<(String, JsonProject)>, D::Error> {
+    struct ProjectsVisitor;
+
+    impl<'de> Visitor<'de> for ProjectsVisitor {
+        type Value = Vec<(String, JsonProject)>;
+
+        fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+            formatter.write_str("a map of project id to project")
+        }
+
+        fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+            let mut projects = Vec::new();
+            while let Some(entry) = map.next_entry()? {
+                projects.push(entry);
+            }
+            Ok(projects)
+        }
+    }
+
+    deserializer.deserialize_map(ProjectsVisitor)
 }
 
 #[derive(Deserialize)]
 struct JsonConfig {
-    projects: HashMap<String, JsonProject>,
+    #[serde(deserialize_with = "deserialize_projects")]
+    projects: Vec<(String, JsonProject)>,
     account_manager_name: String,
     signing_key_path: String,
     signature_folder: String,
     base_url: String,
-    weak_auth: String,
+    #[serde(default)]
+    strict_reply_parsing: bool,
+    #[serde(default)]
+    listen: Vec<SocketAddr>,
+    tls_cert: Option<PathBuf>,
+    tls_key: Option<PathBuf>,
+    /// The planning strategies, in order
+    #[serde(default = "PlannerConfig::default_chain")]
+    planners: Vec<PlannerConfig>,
 }
 
 impl AppState {
-    pub fn new<T: Read>(reader: &mut T, database: DataBase) -> anyhow::Result<Self> {
+    /// Load the config. Every problem found is reported at once. An invalid url signature is only
+    /// a problem when strict, it is otherwise logged as a warning.
+    pub fn new<T: Read>(
+        reader: &mut T,
+        database: DataBase,
+        strict: bool,
+    ) -> Result<Self, ConfigError> {
         let config: JsonConfig =
-            serde_json::from_reader(reader).context("Failed to read the config.json file")?;
+            serde_json::from_reader(reader).map_err(ConfigProblem::InvalidJson)?;
+        let mut problems = Vec::new();
+
+        let signing_key_path = PathBuf::from(&config.signing_key_path);
         let mut signing_key = String::new();
-        File::open(&config.signing_key_path)
-            .unwrap()
-            .read_to_string(&mut signing_key)
-            .unwrap();
+        let mut public_key = None;
+        match File::open(&signing_key_path)
+            .and_then(|mut file| file.read_to_string(&mut signing_key))
+        {
+            Ok(_) => match boinc_crypt::parse_public_key(&signing_key) {
+                Ok(key) => public_key = Some(key),
+                Err(error) => problems.push(ConfigProblem::InvalidSigningKey {
+                    path: signing_key_path,
+                    error,
+                }),
+            },
+            Err(error) => problems.push(ConfigProblem::UnreadableFile {
+                path: signing_key_path,
+                project: None,
+                error,
+            }),
+        }
 
+        let mut proxy_url_count: HashMap<String, usize> = HashMap::new();
         let mut projects = HashMap::new();
         for (project_key, project_data) in &config.projects {
             let project_proxy_url = Self::_get_proxy_url(&config.base_url, project_key);
-            info!("looking for the signature of “{}”", project_proxy_url);
+            *proxy_url_count
+                .entry(project_proxy_url.clone())
+                .or_default() += 1;
+            if project_data.scheduler_url.trim().is_empty() {
+                problems.push(ConfigProblem::EmptyField {
+                    project: project_key.clone(),
+                    field: "scheduler_url",
+                });
+            }
+            if project_data.authenticator.trim().is_empty() {
+                problems.push(ConfigProblem::EmptyField {
+                    project: project_key.clone(),
+                    field: "authenticator",
+                });
+            }
+
+            let eligibility = EligibilityRules::from_json(&project_data.eligibility)
+                .unwrap_or_else(|error| {
+                    problems.push(ConfigProblem::InvalidEligibilityRule {
+                        project: project_key.clone(),
+                        error,
+                    });
+                    EligibilityRules::from_json(&JsonEligibilityRules::default()).unwrap()
+                });
 
+            info!("looking for the signature of “{}”", project_proxy_url);
             let mut url_signature = String::new();
-            let path = Path::new(&config.signature_folder);
-            File::open(&path.join(format!("{}.pub", project_key)))
-                .unwrap()
-                .read_to_string(&mut url_signature)
-                .unwrap();
+            let path = Path::new(&config.signature_folder).join(format!("{}.pub", project_key));
+            if let Err(error) =
+                File::open(&path).and_then(|mut file| file.read_to_string(&mut url_signature))
+            {
+                problems.push(ConfigProblem::UnreadableFile {
+                    path,
+                    project: Some(project_key.clone()),
+                    error,
+                });
+            } else if let Some(public_key) = &public_key {
+                if !boinc_crypt::verify_string_signature(
+                    &project_proxy_url,
+                    &url_signature,
+                    public_key,
+                ) {
+                    let problem = ConfigProblem::InvalidUrlSignature {
+                        project: project_key.clone(),
+                        url: project_proxy_url,
+                    };
+                    if strict {
+                        problems.push(problem);
+                    } else {
+                        warn!("{}", problem);
+                    }
+                }
+            }
 
             projects.insert(
                 project_key.clone(),
@@ -73,21 +218,83 @@ impl AppState {
                     scheduler_url: project_data.scheduler_url.clone(),
                     authenticator: project_data.authenticator.clone(),
                     url_signature,
+                    enabled: project_data.enabled,
+                    detach_when_done: project_data.detach_when_done,
+                    suspend: project_data.suspend,
+                    eligibility,
+                    keywords: project_data.keywords.clone(),
                 },
             );
         }
 
+        let mut duplicated_urls: Vec<_> = proxy_url_count
+            .into_iter()
+            .filter(|(_, count)| *count > 1)
+            .collect();
+        duplicated_urls.sort();
+        for (url, count) in duplicated_urls {
+            problems.push(ConfigProblem::DuplicateProxyUrl { url, count });
+        }
+
+        for (index, planner) in config.planners.iter().enumerate() {
+            if let Err(error) = planner.validate() {
+                problems.push(ConfigProblem::InvalidPlanner { index, error });
+            }
+        }
+
+        if !problems.is_empty() {
+            return Err(ConfigError { problems });
+        }
+
         let result = AppState {
             projects,
             account_manager_name: config.account_manager_name,
-            signing_key: signing_key,
+            signing_key,
             base_url: config.base_url,
             database,
-            weak_auth: config.weak_auth,
+            strict_reply_parsing: config.strict_reply_parsing,
+            listen: config.listen,
+            tls_cert: config.tls_cert,
+            tls_key: config.tls_key,
+            planner: PlannerChain::from_config(&config.planners),
         };
         Ok(result)
     }
 
+    /// Sign the proxy url of every project of the config with private_key, and write them in
+    /// signature_folder. private_key should match the public key at signing_key_path.
+    /// Return the paths of the written signatures.
+    pub fn write_url_signatures<T: Read>(
+        reader: &mut T,
+        private_key: &RsaPrivateKey,
+    ) -> anyhow::Result<Vec<PathBuf>> {
+        let config: JsonConfig =
+            serde_json::from_reader(reader).context("Failed to read the config.json file")?;
+
+        let mut signing_key = String::new();
+        File::open(&config.signing_key_path)
+            .and_then(|mut file| file.read_to_string(&mut signing_key))
+            .with_context(|| format!("Reading the signing key {}", config.signing_key_path))?;
+        if boinc_crypt::parse_public_key(&signing_key)? != private_key.to_public_key() {
+            bail!(
+                "The private key doesn’t match the public key {}",
+                config.signing_key_path
+            );
+        }
+
+        let mut written = Vec::new();
+        for (project_key, _) in &config.projects {
+            let project_proxy_url = Self::_get_proxy_url(&config.base_url, project_key);
+            let signature = boinc_crypt::sign_string(&project_proxy_url, private_key)?;
+            let path = Path::new(&config.signature_folder).join(format!("{}.pub", project_key));
+            std::fs::write(&path, signature)
+                .with_context(|| format!("Writing the signature {}", path.display()))?;
+            info!("signed “{}” in {}", project_proxy_url, path.display());
+            written.push(path);
+        }
+        Ok(written)
+    }
+
     pub fn get_proxy_url(&self, project: &str) -> String {
         Self::_get_proxy_url(&self.base_url, project)
     }
@@ -100,3 +307,173 @@ impl AppState {
         format!("{}/proxy/{}/scheduler", self.base_url, project)
     }
 }
+
+/// The current AppState, that can be replaced by a new one while the server is running.
+/// Request handlers should call get once, to work on a consistent config.
+#[derive(Clone)]
+pub struct SharedAppState {
+    current: Arc<RwLock<Arc<AppState>>>,
+}
+
+impl SharedAppState {
+    pub fn new(app_state: AppState) -> Self {
+        SharedAppState {
+            current: Arc::new(RwLock::new(Arc::new(app_state))),
+        }
+    }
+
+    pub fn get(&self) -> Arc<AppState> {
+        self.current.read().unwrap().clone()
+    }
+
+    /// Load a new config, keeping the same database. check is given the current and the new
+    /// state, and can refuse the new one. The current state is kept if the new config can’t be
+    /// loaded or is refused.
+    pub fn reload<T: Read>(
+        &self,
+        reader: &mut T,
+        strict: bool,
+        check: impl FnOnce(&AppState, &AppState) -> anyhow::Result<()>,
+    ) -> anyhow::Result<()> {
+        let current = self.get();
+        let new_state = AppState::new(reader, current.database.clone(), strict)?;
+        check(&current, &new_state)?;
+        *self.current.write().unwrap() = Arc::new(new_state);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    /// A directory with the public key of the test fixtures, and the signatures of the proxy urls
+    /// of signed_projects
+    fn config_dir(signed_projects: &[&str]) -> tempfile::TempDir {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(
+            dir.path().join("public_key"),
+            include_str!("fixtures/test_public_key"),
+        )
+        .unwrap();
+        std::fs::create_dir(dir.path().join("signatures")).unwrap();
+        let private_key =
+            boinc_crypt::parse_private_key(include_str!("fixtures/test_private_key")).unwrap();
+        for project in signed_projects {
+            let url = AppState::_get_proxy_url("https://bam.example.com", project);
+            std::fs::write(
+                dir.path()
+                    .join("signatures")
+                    .join(format!("{}.pub", project)),
+                boinc_crypt::sign_string(&url, &private_key).unwrap(),
+            )
+            .unwrap();
+        }
+        dir
+    }
+
+    fn config(dir: &tempfile::TempDir, projects: &str) -> String {
+        format!(
+            r#"{{
+                "account_manager_name": "test",
+                "signing_key_path": {},
+                "signature_folder": {},
+                "base_url": "https://bam.example.com",
+                "projects": {}
+            }}"#,
+            json!(dir.path().join("public_key")),
+            json!(dir.path().join("signatures")),
+            projects
+        )
+    }
+
+    fn project(authenticator: &str) -> String {
+        json!({
+            "name": "A project",
+            "scheduler_url": "https://project.example.com/cgi-bin/cgi",
+            "authenticator": authenticator,
+        })
+        .to_string()
+    }
+
+    fn load(config: &str, strict: bool) -> Result<AppState, ConfigError> {
+        AppState::new(
+            &mut config.as_bytes(),
+            DataBase::new_in_memory().unwrap(),
+            strict,
+        )
+    }
+
+    fn problems(config: &str, strict: bool) -> Vec<ConfigProblem> {
+        match load(config, strict) {
+            Ok(_) => panic!("the config loaded"),
+            Err(err) => err.problems,
+        }
+    }
+
+    #[test]
+    fn valid_config() {
+        let dir = config_dir(&["a"]);
+        let state = load(
+            &config(&dir, &format!(r#"{{"a": {}}}"#, project("key"))),
+            true,
+        )
+        .unwrap();
+        assert_eq!(state.projects.len(), 1);
+    }
+
+    #[test]
+    fn every_problem_is_reported() {
+        let dir = config_dir(&["a", "dup"]);
+        let projects = format!(
+            r#"{{"a": {}, "unsigned": {}, "dup": {}, "dup": {}}}"#,
+            project(""),
+            project("key"),
+            project("key"),
+            project("key")
+        );
+        let problems = problems(&config(&dir, &projects), false);
+        assert_eq!(problems.len(), 3, "{:?}", problems);
+        assert!(matches!(
+            &problems[0],
+            ConfigProblem::EmptyField { project, field: "authenticator" } if project == "a"
+        ));
+        assert!(matches!(
+            &problems[1],
+            ConfigProblem::UnreadableFile { project: Some(project), .. } if project == "unsigned"
+        ));
+        assert!(matches!(
+            &problems[2],
+            ConfigProblem::DuplicateProxyUrl { count: 2, .. }
+        ));
+    }
+
+    #[test]
+    fn invalid_signatures_only_fail_when_strict() {
+        let dir = config_dir(&["a"]);
+        std::fs::copy(
+            dir.path().join("signatures/a.pub"),
+            dir.path().join("signatures/b.pub"),
+        )
+        .unwrap();
+        let config = config(
+            &dir,
+            &format!(r#"{{"a": {}, "b": {}}}"#, project("key"), project("key")),
+        );
+
+        assert_eq!(load(&config, false).unwrap().projects.len(), 2);
+        let problems = problems(&config, true);
+        assert_eq!(problems.len(), 1);
+        assert!(matches!(
+            &problems[0],
+            ConfigProblem::InvalidUrlSignature { project, .. } if project == "b"
+        ));
+    }
+
+    #[test]
+    fn invalid_json() {
+        let problems = problems("{", false);
+        assert!(matches!(problems[..], [ConfigProblem::InvalidJson(_)]));
+    }
+}
@@ -4,9 +4,15 @@ pub use project_config::get_project_config;
 mod xml_response;
 pub use xml_response::xml_to_response;
 
+mod xml_sanitizer;
+pub use xml_sanitizer::sanitize_xml;
+
 mod rpc;
 pub use rpc::rpc_endpoint;
 
+mod proxy_error;
+pub use proxy_error::ProxyError;
+
 mod proxy_scheduler;
 pub use proxy_scheduler::proxy_scheduler_route;
 
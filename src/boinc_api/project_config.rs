@@ -14,7 +14,7 @@ struct GetProjectResult {
 #[get("/get_project_config.php")]
 pub async fn get_project_config() -> Result<HttpResponse> {
     //TODO: at least check the password against the list of valid password
-    Ok(xml_to_response(
+    xml_to_response(
         GetProjectResult {
             name: "Test Project Manager".to_string(),
             min_passwd_length: 1,
@@ -23,5 +23,5 @@ pub async fn get_project_config() -> Result<HttpResponse> {
             client_acount_creation_disabled: Some(()),
         },
         "project_config",
-    )?)
+    )
 }
@@ -0,0 +1,86 @@
+use std::fmt;
+
+use actix_web::{http::StatusCode, HttpResponse, ResponseError};
+use awc::error::{PayloadError, SendRequestError};
+use log::warn;
+use serde::Serialize;
+
+/// An error while proxying a scheduler request. It is returned to the BOINC client as a valid
+/// `<scheduler_reply>`, so it display the message and back off instead of retrying right away.
+#[derive(Debug)]
+pub enum ProxyError {
+    UnknownProject(String),
+    MissingUserAgent,
+    InvalidRequest(quick_xml::DeError),
+    UpstreamUnreachable(SendRequestError),
+    UpstreamBody(PayloadError),
+    InvalidReply(quick_xml::DeError),
+}
+
+impl ProxyError {
+    /// Number of seconds the client should wait before contacting the scheduler again
+    pub fn request_delay(&self) -> u64 {
+        match self {
+            Self::UnknownProject(_) => 24 * 3600,
+            Self::MissingUserAgent | Self::InvalidRequest(_) | Self::InvalidReply(_) => 3600,
+            Self::UpstreamUnreachable(_) | Self::UpstreamBody(_) => 600,
+        }
+    }
+}
+
+impl fmt::Display for ProxyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnknownProject(project_id) => {
+                write!(f, "The account manager has no project “{}”", project_id)
+            }
+            Self::MissingUserAgent => write!(f, "No user-agent provided"),
+            Self::InvalidRequest(err) => write!(f, "Can’t parse the scheduler request: {}", err),
+            Self::UpstreamUnreachable(err) => {
+                write!(f, "Can’t reach the project scheduler: {}", err)
+            }
+            Self::UpstreamBody(err) => {
+                write!(f, "Can’t read the project scheduler reply: {}", err)
+            }
+            Self::InvalidReply(err) => {
+                write!(f, "Can’t parse the project scheduler reply: {}", err)
+            }
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct SchedulerMessage {
+    #[serde(rename = "@priority")]
+    priority: &'static str,
+    #[serde(rename = "$text")]
+    content: String,
+}
+
+#[derive(Serialize)]
+struct SchedulerErrorReply {
+    message: SchedulerMessage,
+    request_delay: u64,
+}
+
+impl ResponseError for ProxyError {
+    /// The BOINC client only read the body of successful replies
+    fn status_code(&self) -> StatusCode {
+        StatusCode::OK
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        warn!("scheduler proxy error: {}", self);
+        let reply = SchedulerErrorReply {
+            message: SchedulerMessage {
+                priority: "high",
+                content: format!("Account manager proxy: {}", self),
+            },
+            request_delay: self.request_delay(),
+        };
+        match quick_xml::se::to_string_with_root("scheduler_reply", &reply) {
+            Ok(body) => HttpResponse::Ok().body(body),
+            Err(_) => HttpResponse::InternalServerError().body("Encoding the XML"),
+        }
+    }
+}
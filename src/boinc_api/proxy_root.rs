@@ -4,13 +4,14 @@ use actix_web::{
     HttpResponse,
 };
 
-use crate::AppState;
+use crate::SharedAppState;
 
 #[get("/proxy/{project_id}/")]
 pub async fn proxy_root_route(
     project: web::Path<String>,
-    app_state: Data<AppState>,
+    app_state: Data<SharedAppState>,
 ) -> HttpResponse {
+    let app_state = app_state.get();
     let scheduler_url = app_state.get_scheduler_url(&project);
     HttpResponse::Ok().body(format!(
         r#"<!DOCTYPE html>
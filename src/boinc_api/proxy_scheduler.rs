@@ -1,4 +1,4 @@
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::collections::HashMap;
 
 use actix_web::{
     http::StatusCode,
@@ -7,10 +7,18 @@ use actix_web::{
     HttpRequest, HttpResponse, HttpResponseBuilder,
 };
 use awc::Client;
+use log::{debug, warn};
 use serde::Deserialize;
-use log::debug;
 
-use crate::{database::WorkUnit, device_info::HostInfo, AppState, AppVersion};
+use crate::{
+    boinc_api::{sanitize_xml, ProxyError},
+    database::{
+        run_blocking, unix_timestamp, ProxyParseFailure, ResultReport, SchedulerExchange, Task,
+        WorkUnit,
+    },
+    device_info::{AltPlatform, HostInfo},
+    AppVersion, AppVersionFile, DeviceInfo, ResultState, SharedAppState,
+};
 
 #[derive(Deserialize, Debug)]
 pub struct SchedulerWorkUnit {
@@ -46,6 +54,22 @@ pub struct SchedulerApp {
     user_friendly_name: String,
 }
 
+#[derive(Deserialize, Debug)]
+pub struct SchedulerCoproc {
+    #[serde(rename = "type")]
+    coproc_type: String,
+    #[serde(default)]
+    count: f64,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct SchedulerFileRef {
+    file_name: String,
+    #[serde(default)]
+    open_name: String,
+    main_program: Option<()>,
+}
+
 #[derive(Deserialize, Debug)]
 pub struct SchedulerAppVersion {
     app_name: String,
@@ -53,6 +77,24 @@ pub struct SchedulerAppVersion {
     platform: String,
     #[serde(default)]
     plan_class: String,
+    /// CPUs used by a task on this host
+    avg_ncpus: Option<f64>,
+    /// speed of a task on this host, in FLOPS
+    flops: Option<f64>,
+    coproc: Option<SchedulerCoproc>,
+    #[serde(default)]
+    gpu_ram: f64,
+    #[serde(default)]
+    file_ref: Vec<SchedulerFileRef>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct SchedulerFileInfo {
+    name: String,
+    #[serde(default)]
+    nbytes: f64,
+    #[serde(default)]
+    md5_cksum: String,
 }
 
 #[derive(Deserialize, Debug)]
@@ -65,12 +107,25 @@ pub struct SchedulerReply {
     app: Vec<SchedulerApp>,
     #[serde(default)]
     app_version: Vec<SchedulerAppVersion>,
+    #[serde(default)]
+    file_info: Vec<SchedulerFileInfo>,
 }
 
+/// A finished task reported by the host
 #[derive(Deserialize, Debug)]
 pub struct ResultQuery {
     name: String,
-    state: u64,
+    state: ResultState,
+    /// CPU time, in seconds
+    #[serde(default)]
+    final_cpu_time: f64,
+    /// wall clock time, in seconds
+    #[serde(default)]
+    final_elapsed_time: f64,
+    #[serde(default)]
+    exit_status: i32,
+    /// only sent by the applications that count their FLOPs
+    fpops_cumulative: Option<f64>,
 }
 
 #[derive(Deserialize, Debug)]
@@ -78,6 +133,10 @@ pub struct Query {
     #[serde(default)]
     result: Vec<ResultQuery>,
     host_info: HostInfo,
+    #[serde(default)]
+    platform_name: String,
+    #[serde(default)]
+    alt_platform: Vec<AltPlatform>,
 }
 
 //TODO: check authentification
@@ -86,95 +145,334 @@ pub async fn proxy_scheduler_route(
     request: HttpRequest,
     source_body: String,
     path: web::Path<String>,
-    app_state: Data<AppState>,
-) -> HttpResponse {
-    //TODO: convert all error to boinc-readable error (except 404 maybe)
+    app_state: Data<SharedAppState>,
+) -> Result<HttpResponse, ProxyError> {
+    let app_state = app_state.get();
     debug!("source:\n{}", source_body);
     let project_id = path.into_inner();
-    let project = if let Some(v) = app_state.projects.get(&project_id) {
-        v
-    } else {
-        return HttpResponse::NotFound().body("Project not found");
-    };
+    let project = app_state
+        .projects
+        .get(&project_id)
+        .ok_or_else(|| ProxyError::UnknownProject(project_id.clone()))?;
 
-    let user_agent = if let Some(ua) = request.headers().get("User-Agent") {
-        ua
-    } else {
-        return HttpResponse::BadRequest().body("No user-agent provided");
-    };
+    let user_agent = request
+        .headers()
+        .get("User-Agent")
+        .ok_or(ProxyError::MissingUserAgent)?;
 
-    let query_analyzed: Query = quick_xml::de::from_str(&source_body).unwrap();
-    for result in &query_analyzed.result {
-        app_state
-            .database
-            .update_status(&project_id, &result.name, result.state)
-            .unwrap();
-    }
+    let query_analyzed: Query =
+        quick_xml::de::from_str(&source_body).map_err(ProxyError::InvalidRequest)?;
     debug!("{:?}", query_analyzed);
-
-    //TODO: get rid of unwrap
+    let timestamp = unix_timestamp();
+    let reports: Vec<ResultReport> = query_analyzed
+        .result
+        .iter()
+        .map(|result| ResultReport {
+            project: project_id.clone(),
+            result_name: result.name.clone(),
+            status: result.state,
+            final_cpu_time: result.final_cpu_time,
+            final_elapsed_time: result.final_elapsed_time,
+            exit_status: result.exit_status,
+            fpops_cumulative: result.fpops_cumulative,
+            reported_time: timestamp,
+        })
+        .collect();
+    let device_info = DeviceInfo::new(
+        query_analyzed.host_info,
+        &query_analyzed.platform_name,
+        &query_analyzed.alt_platform,
+    );
+    let cpid = device_info.host_info.host_cpid.clone();
+    // every proxied request record the host, even when the project can’t be reached
+    let database = app_state.database.clone();
+    let host_id = match run_blocking(move || database.upsert_host(&device_info, timestamp)).await {
+        Ok(host_id) => Some(host_id),
+        Err(err) => {
+            warn!(
+                "can’t record the host of a request to {}: {:#}",
+                project_id, err
+            );
+            None
+        }
+    };
     let mut res = Client::default()
         .post(&project.scheduler_url)
         .insert_header(("User-Agent", user_agent))
         .send_body(source_body)
         .await
-        .unwrap();
+        .map_err(ProxyError::UpstreamUnreachable)?;
 
-    //TODO: avoid unwraping
-    let result_body = res.body().await.unwrap();
+    let result_body = res.body().await.map_err(ProxyError::UpstreamBody)?;
 
     debug!("result\n{:?}", result_body);
 
+    // the reports are only acknowledged by a valid reply, the client send them again otherwise
+    let mut acknowledged = Vec::new();
+    let mut records = (Vec::new(), Vec::new(), Vec::new());
+    let mut parse_failure = None;
+    let mut invalid_reply = None;
     if res.status() == StatusCode::OK {
-        let result_string = String::from_utf8_lossy(&result_body).replace("&", "&amp;"); // The server doesn’t seems to escape it, which is invalid XML!!
-        let result: SchedulerReply = quick_xml::de::from_str(&result_string).unwrap();
-
-        for workunit in &result.workunit {
-            for res in &result.result {
-                if res.wu_name == workunit.name {
-                    let merged_wu = WorkUnit {
-                        cpid: query_analyzed.host_info.host_cpid.clone(),
-                        project: project_id.clone(),
-                        name: workunit.name.to_string(),
-                        status: 1,
-                        app_name: workunit.app_name.to_string(),
-                        rsc_fpops_est: workunit.rsc_fpops_est,
-                        rsc_fpops_bound: workunit.rsc_fpops_bound,
-                        rsc_memory_bound: workunit.rsc_memory_bound,
-                        rsc_disk_bound: workunit.rsc_disk_bound,
-                        platform: res.platform.to_string(),
-                        version_num: res.version_num,
-                        plan_class: res.plan_class.to_string(),
-                        result_name: res.name.to_string(),
-                        timestamp: SystemTime::now()
-                            .duration_since(UNIX_EPOCH)
-                            .unwrap()
-                            .as_secs(),
-                    };
-                    app_state.database.add_work_unit(&merged_wu).unwrap();
-                    break;
-                }
+        let result_string = sanitize_xml(&result_body);
+        match quick_xml::de::from_str::<SchedulerReply>(&result_string) {
+            Ok(result) => {
+                records = reply_records(&project_id, &cpid, timestamp, &result);
+                acknowledged = reports;
             }
-        }
-
-        for app_version in &result.app_version {
-            for app in &result.app {
-                if app.name == app_version.app_name {
-                    let merged_app = AppVersion {
-                        project: project_id.clone(),
-                        app_name: app.name.clone(),
-                        user_friendly_name: app.user_friendly_name.clone(),
-                        version: app_version.version_num,
-                        platform: app_version.platform.clone(),
-                        plan_class: app_version.plan_class.clone(),
-                    };
-                    app_state.database.add_app_version(&merged_app).unwrap();
-                    break;
-                };
+            Err(err) if app_state.strict_reply_parsing => invalid_reply = Some(err),
+            Err(err) => {
+                warn!(
+                    "can’t parse the scheduler reply of {}, forwarding it as-is: {}",
+                    project_id, err
+                );
+                parse_failure = Some(ProxyParseFailure::new(
+                    &project_id,
+                    timestamp,
+                    err.to_string(),
+                    &String::from_utf8_lossy(&result_body),
+                ));
+                acknowledged = reports;
             }
         }
-        //println!("{:?}", result);
+    }
+    // the reply goes back to the client even if it can’t be recorded
+    if let Some(host_id) = host_id {
+        let (workunits, tasks, app_versions) = records;
+        let exchange = SchedulerExchange {
+            host_id,
+            timestamp,
+            reports: acknowledged,
+            workunits,
+            tasks,
+            app_versions,
+            parse_failure,
+        };
+        let database = app_state.database.clone();
+        if let Err(err) = run_blocking(move || database.record_exchange(&exchange)).await {
+            warn!(
+                "can’t record the scheduler exchange with {}: {:#}",
+                project_id, err
+            );
+        }
+    }
+    if let Some(err) = invalid_reply {
+        return Err(ProxyError::InvalidReply(err));
     }
 
-    HttpResponseBuilder::new(res.status()).body(result_body)
+    Ok(HttpResponseBuilder::new(res.status()).body(result_body))
+}
+
+/// The work units, tasks and app versions sent to the host in a scheduler reply. A task is kept
+/// even if its work unit isn’t in the reply, an app version whose app isn’t in the reply is
+/// dropped. Only the `<file_info>` of the app versions are kept, not those of the work units. The
+/// tasks get the CPU and speed estimates of their app version.
+fn reply_records(
+    project_id: &str,
+    cpid: &str,
+    timestamp: u64,
+    reply: &SchedulerReply,
+) -> (Vec<WorkUnit>, Vec<Task>, Vec<AppVersion>) {
+    let workunits: Vec<WorkUnit> = reply
+        .workunit
+        .iter()
+        .map(|workunit| WorkUnit {
+            project: project_id.to_string(),
+            name: workunit.name.clone(),
+            app_name: workunit.app_name.clone(),
+            rsc_fpops_est: workunit.rsc_fpops_est,
+            rsc_fpops_bound: workunit.rsc_fpops_bound,
+            rsc_memory_bound: workunit.rsc_memory_bound,
+            rsc_disk_bound: workunit.rsc_disk_bound,
+        })
+        .collect();
+    let workunit_apps: HashMap<&str, &str> = workunits
+        .iter()
+        .map(|workunit| (workunit.name.as_str(), workunit.app_name.as_str()))
+        .collect();
+    // the estimates of an app version are made for this host, so they are kept with its tasks
+    let app_versions_by_key: HashMap<(&str, u64, &str, &str), &SchedulerAppVersion> = reply
+        .app_version
+        .iter()
+        .map(|app_version| {
+            (
+                (
+                    app_version.app_name.as_str(),
+                    app_version.version_num,
+                    app_version.platform.as_str(),
+                    app_version.plan_class.as_str(),
+                ),
+                app_version,
+            )
+        })
+        .collect();
+    let tasks = reply
+        .result
+        .iter()
+        .map(|result| {
+            let app_version = workunit_apps
+                .get(result.wu_name.as_str())
+                .and_then(|app_name| {
+                    app_versions_by_key.get(&(
+                        *app_name,
+                        result.version_num,
+                        result.platform.as_str(),
+                        result.plan_class.as_str(),
+                    ))
+                });
+            Task {
+                project: project_id.to_string(),
+                result_name: result.name.clone(),
+                workunit_name: result.wu_name.clone(),
+                cpid: cpid.to_string(),
+                host_id: None,
+                status: ResultState::FilesDownloading,
+                platform: result.platform.clone(),
+                version_num: result.version_num,
+                plan_class: result.plan_class.clone(),
+                timestamp,
+                missing_workunit: !workunit_apps.contains_key(result.wu_name.as_str()),
+                avg_ncpus: app_version.and_then(|app_version| app_version.avg_ncpus),
+                flops: app_version.and_then(|app_version| app_version.flops),
+                final_cpu_time: None,
+                final_elapsed_time: None,
+                exit_status: None,
+                fpops_cumulative: None,
+                reported_time: None,
+            }
+        })
+        .collect();
+
+    let apps_by_name: HashMap<&str, &SchedulerApp> = reply
+        .app
+        .iter()
+        .map(|app| (app.name.as_str(), app))
+        .collect();
+    let file_infos_by_name: HashMap<&str, &SchedulerFileInfo> = reply
+        .file_info
+        .iter()
+        .map(|file_info| (file_info.name.as_str(), file_info))
+        .collect();
+    let app_versions = reply
+        .app_version
+        .iter()
+        .filter_map(|app_version| {
+            let app = apps_by_name.get(app_version.app_name.as_str())?;
+            let files = app_version
+                .file_ref
+                .iter()
+                .map(|file_ref| {
+                    let file_info = file_infos_by_name.get(file_ref.file_name.as_str());
+                    AppVersionFile {
+                        name: file_ref.file_name.clone(),
+                        open_name: file_ref.open_name.clone(),
+                        main_program: file_ref.main_program.is_some(),
+                        nbytes: file_info.map(|file_info| file_info.nbytes),
+                        md5_cksum: file_info.map(|file_info| file_info.md5_cksum.clone()),
+                    }
+                })
+                .collect();
+            Some(AppVersion {
+                project: project_id.to_string(),
+                app_name: app.name.clone(),
+                user_friendly_name: app.user_friendly_name.clone(),
+                version: app_version.version_num,
+                platform: app_version.platform.clone(),
+                plan_class: app_version.plan_class.clone(),
+                coproc_type: app_version
+                    .coproc
+                    .as_ref()
+                    .map(|coproc| coproc.coproc_type.clone()),
+                coproc_count: app_version
+                    .coproc
+                    .as_ref()
+                    .map_or(0.0, |coproc| coproc.count),
+                gpu_ram: app_version.gpu_ram,
+                files,
+            })
+        })
+        .collect();
+    (workunits, tasks, app_versions)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const REPLY: &str = "<scheduler_reply>
+        <app><name>app1</name><user_friendly_name>App 1</user_friendly_name></app>
+        <app_version>
+            <app_name>app1</app_name><version_num>7</version_num><platform>x86_64-pc-linux-gnu</platform><plan_class>cuda</plan_class>
+            <avg_ncpus>0.5</avg_ncpus><flops>1e11</flops><gpu_ram>1e9</gpu_ram>
+            <coproc><type>NVIDIA</type><count>1.000000</count></coproc>
+            <file_ref><file_name>app1_7_cuda</file_name><main_program/></file_ref>
+            <file_ref><file_name>libcudart.so</file_name><open_name>libcudart.so.12</open_name></file_ref>
+        </app_version>
+        <file_info><name>app1_7_cuda</name><url>https://example.com/app1_7_cuda</url><executable/><nbytes>1000000.000000</nbytes><md5_cksum>d41d8cd98f00b204e9800998ecf8427e</md5_cksum></file_info>
+        <file_info><name>input.zip</name><nbytes>5000.000000</nbytes><md5_cksum>0cc175b9c0f1b6a831c399e269772661</md5_cksum></file_info>
+        <app_version><app_name>unknown</app_name><version_num>1</version_num><platform>x86_64-pc-linux-gnu</platform></app_version>
+        <workunit><name>wu1</name><app_name>app1</app_name><rsc_fpops_est>1e12</rsc_fpops_est></workunit>
+        <result><name>wu1_0</name><wu_name>wu1</wu_name><platform>x86_64-pc-linux-gnu</platform><version_num>7</version_num><plan_class>cuda</plan_class></result>
+        <result><name>wu1_1</name><wu_name>wu1</wu_name><platform>x86_64-pc-linux-gnu</platform><version_num>7</version_num><plan_class></plan_class></result>
+        <result><name>wu2_0</name><wu_name>wu2</wu_name><platform>x86_64-pc-linux-gnu</platform><version_num>7</version_num><plan_class></plan_class></result>
+    </scheduler_reply>";
+
+    #[test]
+    fn merge_reply() {
+        let reply: SchedulerReply = quick_xml::de::from_str(REPLY).unwrap();
+        let (workunits, tasks, app_versions) = reply_records("p", "cpid", 42, &reply);
+
+        assert_eq!(workunits.len(), 1);
+        assert_eq!(workunits[0].name, "wu1");
+        assert_eq!(workunits[0].rsc_fpops_est, 1e12);
+
+        let names: Vec<(&str, &str)> = tasks
+            .iter()
+            .map(|task| (task.result_name.as_str(), task.workunit_name.as_str()))
+            .collect();
+        assert_eq!(
+            names,
+            [("wu1_0", "wu1"), ("wu1_1", "wu1"), ("wu2_0", "wu2")]
+        );
+        let missing: Vec<bool> = tasks.iter().map(|task| task.missing_workunit).collect();
+        assert_eq!(missing, [false, false, true]);
+        // only the task of the cuda app version get its estimates
+        let estimates: Vec<(Option<f64>, Option<f64>)> = tasks
+            .iter()
+            .map(|task| (task.avg_ncpus, task.flops))
+            .collect();
+        assert_eq!(
+            estimates,
+            [(Some(0.5), Some(1e11)), (None, None), (None, None)]
+        );
+        assert!(tasks
+            .iter()
+            .all(|task| task.project == "p" && task.cpid == "cpid" && task.timestamp == 42));
+
+        assert_eq!(app_versions.len(), 1);
+        assert_eq!(app_versions[0].app_name, "app1");
+        assert_eq!(app_versions[0].user_friendly_name, "App 1");
+        assert_eq!(app_versions[0].version, 7);
+        assert_eq!(app_versions[0].coproc_type.as_deref(), Some("NVIDIA"));
+        assert_eq!(app_versions[0].coproc_count, 1.0);
+        assert_eq!(app_versions[0].gpu_ram, 1e9);
+        let files: Vec<(&str, &str, bool, Option<f64>)> = app_versions[0]
+            .files
+            .iter()
+            .map(|file| {
+                (
+                    file.name.as_str(),
+                    file.open_name.as_str(),
+                    file.main_program,
+                    file.nbytes,
+                )
+            })
+            .collect();
+        assert_eq!(
+            files,
+            [
+                ("app1_7_cuda", "", true, Some(1e6)),
+                ("libcudart.so", "libcudart.so.12", false, None),
+            ]
+        );
+    }
 }
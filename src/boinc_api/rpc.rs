@@ -1,10 +1,41 @@
 use crate::{
-    boinc_api::xml_to_response, device_info::HostInfo, planify_action, AppState, DeviceInfo,
-    PlanificatorResult,
+    boinc_api::xml_to_response,
+    database::{run_blocking, unix_timestamp},
+    device_info::{AltPlatform, HostInfo},
+    planify_action, AppState, DeviceInfo, PlanificatorResult, SharedAppState,
 };
 use actix_web::{post, web::Data, HttpResponse, Result};
+use log::{info, warn};
 use serde::{Deserialize, Serialize};
 
+/// BOINC’s ERR_XML_PARSE
+const ERR_XML_PARSE: i32 = -112;
+/// BOINC’s ERR_DB_NOT_FOUND
+const ERR_DB_NOT_FOUND: i32 = -136;
+/// BOINC’s ERR_DB_CANT_CONNECT
+const ERR_DB_CANT_CONNECT: i32 = -138;
+/// BOINC’s ERR_BAD_PASSWD
+const ERR_BAD_PASSWD: i32 = -206;
+
+#[derive(Serialize)]
+pub struct RpcError {
+    error_num: i32,
+    error_msg: String,
+}
+
+impl RpcError {
+    /// The reply shown to the user by the BOINC client
+    fn response(error_num: i32, error_msg: &str) -> Result<HttpResponse> {
+        xml_to_response(
+            RpcError {
+                error_num,
+                error_msg: error_msg.to_string(),
+            },
+            "acct_mgr_reply",
+        )
+    }
+}
+
 #[derive(Serialize)]
 pub struct RpcAccount {
     url: String,
@@ -12,6 +43,9 @@ pub struct RpcAccount {
     authenticator: String,
     resource_share: u16,
     detach: u8,
+    detach_when_done: u8,
+    dont_request_work: u8,
+    suspend: u8,
 }
 
 #[derive(Serialize)]
@@ -28,16 +62,19 @@ impl RpcResponse {
     ) -> Result<Self> {
         let mut account = Vec::new();
         for (project_id, project) in &app_state.projects {
-            let mut priority = 0;
-            if let Some(project_plan) = plan_result.projects.get(project_id) {
-                priority = project_plan.priority;
-            }
+            // projects left out of the plan are detached, a priority of 0 only make them backup
+            // projects
+            let planned = plan_result.projects.get(project_id);
+            let priority = planned.map_or(0, |project_plan| project_plan.priority);
             account.push(RpcAccount {
-                url: app_state.get_proxy_url(&project_id),
+                url: app_state.get_proxy_url(project_id),
                 url_signature: project.url_signature.clone(),
                 authenticator: project.authenticator.clone(),
                 resource_share: priority,
-                detach: if priority == 0 { 1 } else { 0 },
+                detach: u8::from(!project.enabled || planned.is_none()),
+                detach_when_done: u8::from(project.detach_when_done),
+                dont_request_work: u8::from(project.detach_when_done),
+                suspend: u8::from(project.suspend),
             });
         }
         Ok(RpcResponse {
@@ -51,21 +88,64 @@ impl RpcResponse {
 #[derive(Deserialize)]
 pub struct RpcQuery {
     name: String,
+    #[serde(default)]
+    password_hash: String,
     host_info: HostInfo,
+    #[serde(default)]
+    platform_name: String,
+    #[serde(default)]
+    alt_platform: Vec<AltPlatform>,
 }
 
 #[post("/rpc.php")]
-pub async fn rpc_endpoint(post: String, app_state: Data<AppState>) -> Result<HttpResponse> {
-    let rpc_query: RpcQuery = quick_xml::de::from_str(&post).unwrap();
-    if rpc_query.name != app_state.weak_auth {
-        return Ok(HttpResponse::Forbidden().body("Invalid name"));
+pub async fn rpc_endpoint(post: String, app_state: Data<SharedAppState>) -> Result<HttpResponse> {
+    let app_state = app_state.get();
+    let rpc_query: RpcQuery = match quick_xml::de::from_str(&post) {
+        Ok(rpc_query) => rpc_query,
+        Err(err) => {
+            info!("invalid rpc query: {}", err);
+            return RpcError::response(ERR_XML_PARSE, "Invalid request");
+        }
+    };
+    let database = app_state.database.clone();
+    let name = rpc_query.name.clone();
+    let user = match run_blocking(move || database.get_user(&name)).await {
+        Ok(Some(user)) => user,
+        Ok(None) => {
+            info!("rpc query from unknown user “{}”", rpc_query.name);
+            return RpcError::response(ERR_DB_NOT_FOUND, "Unknown user name");
+        }
+        Err(err) => {
+            warn!("can’t look up the user “{}”: {:#}", rpc_query.name, err);
+            return RpcError::response(ERR_DB_CANT_CONNECT, "Account manager database error");
+        }
+    };
+    if !user.check_password_hash(&rpc_query.password_hash) {
+        info!("rpc query with an invalid password for “{}”", user.name);
+        return RpcError::response(ERR_BAD_PASSWD, "Invalid password");
     }
-    let plan_result = planify_action(
-        &app_state,
-        &DeviceInfo {
-            host_info: rpc_query.host_info,
-        },
+    let device_info = DeviceInfo::new(
+        rpc_query.host_info,
+        &rpc_query.platform_name,
+        &rpc_query.alt_platform,
     );
+    let planner_state = app_state.clone();
+    let user_name = user.name.clone();
+    let plan_result = run_blocking(move || {
+        let host_id = planner_state
+            .database
+            .upsert_host(&device_info, unix_timestamp())?;
+        planner_state.database.set_host_user(host_id, &user.name)?;
+        Ok(planify_action(&planner_state, &device_info))
+    })
+    .await;
+    let plan_result = match plan_result {
+        Ok(plan_result) => plan_result,
+        Err(err) => {
+            warn!("can’t save the host of “{}”: {:#}", user_name, err);
+            return RpcError::response(ERR_DB_CANT_CONNECT, "Account manager database error");
+        }
+    };
     let result = RpcResponse::new_from_planificator_result(&app_state, &plan_result)?;
 
     xml_to_response(result, "acct_mgr_reply")
@@ -0,0 +1,164 @@
+/// Turn a scheduler reply into something quick-xml accept. Some projects send bare `&` (often in
+/// URLs), stray control characters or non-UTF-8 text. This:
+/// - replace invalid UTF-8 sequences with U+FFFD,
+/// - remove characters that are not allowed in XML 1.0 (every C0 control character except tab,
+///   newline and carriage return, U+FFFE and U+FFFF),
+/// - escape every `&` that doesn’t start one of the predefined entities or a valid character
+///   reference.
+///
+/// Comments and CDATA sections are copied as-is (minus the invalid characters).
+pub fn sanitize_xml(body: &[u8]) -> String {
+    let source = String::from_utf8_lossy(body);
+    let mut result = String::with_capacity(source.len());
+    let mut remaining: &str = &source;
+
+    while let Some(position) = remaining.find(['&', '<']) {
+        push_valid_chars(&mut result, &remaining[..position]);
+        remaining = &remaining[position..];
+
+        if remaining.starts_with('&') {
+            let entity_len = valid_entity_len(remaining);
+            if entity_len > 0 {
+                result.push_str(&remaining[..entity_len]);
+                remaining = &remaining[entity_len..];
+            } else {
+                result.push_str("&amp;");
+                remaining = &remaining[1..];
+            }
+            continue;
+        }
+
+        let verbatim_end = if remaining.starts_with("<!--") {
+            remaining.find("-->").map(|end| end + 3)
+        } else if remaining.starts_with("<![CDATA[") {
+            remaining.find("]]>").map(|end| end + 3)
+        } else {
+            Some(1)
+        };
+        // an unterminated section is kept until the end, quick-xml will report it
+        let verbatim_end = verbatim_end.unwrap_or(remaining.len());
+        push_valid_chars(&mut result, &remaining[..verbatim_end]);
+        remaining = &remaining[verbatim_end..];
+    }
+    push_valid_chars(&mut result, remaining);
+
+    result
+}
+
+fn is_valid_xml_char(c: char) -> bool {
+    matches!(c, '\t' | '\n' | '\r' | '\u{20}'..='\u{D7FF}' | '\u{E000}'..='\u{FFFD}' | '\u{10000}'..)
+}
+
+fn push_valid_chars(result: &mut String, text: &str) {
+    result.extend(text.chars().filter(|c| is_valid_xml_char(*c)));
+}
+
+/// Return the length of the entity or character reference at the start of text (that start with
+/// `&`), or 0 if it isn’t a valid one
+fn valid_entity_len(text: &str) -> usize {
+    // the longest valid reference is `&#x10FFFF;`
+    let end = match text.char_indices().take(11).find(|(_, c)| *c == ';') {
+        Some((end, _)) => end,
+        None => return 0,
+    };
+    let name = &text[1..end];
+    let valid = match name {
+        "amp" | "lt" | "gt" | "quot" | "apos" => true,
+        _ => {
+            let code = if let Some(hex) = name.strip_prefix("#x") {
+                u32::from_str_radix(hex, 16).ok()
+            } else if let Some(decimal) = name.strip_prefix('#') {
+                decimal.parse::<u32>().ok()
+            } else {
+                None
+            };
+            code.and_then(char::from_u32)
+                .map(is_valid_xml_char)
+                .unwrap_or(false)
+        }
+    };
+    if valid {
+        end + 1
+    } else {
+        0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use quick_xml::{events::Event, Reader};
+
+    /// Read the whole document, unescaping every text, and return the concatenated text
+    fn read_all_text(xml: &str) -> String {
+        let mut reader = Reader::from_str(xml);
+        let mut text = String::new();
+        loop {
+            match reader.read_event().unwrap() {
+                Event::Text(t) => text.push_str(&t.unescape().unwrap()),
+                Event::CData(t) => text.push_str(&String::from_utf8_lossy(&t)),
+                Event::Start(start) | Event::Empty(start) => {
+                    for attribute in start.attributes() {
+                        attribute.unwrap().unescape_value().unwrap();
+                    }
+                }
+                Event::Eof => break,
+                _ => (),
+            }
+        }
+        text
+    }
+
+    #[test]
+    fn bare_ampersand_in_reply() {
+        let fixture = include_bytes!("fixtures/scheduler_reply_bare_ampersand.xml");
+        let sanitized = sanitize_xml(fixture);
+        let text = read_all_text(&sanitized);
+        assert!(text.contains("download.php?file=mcm1_7.61&platform=x86_64-pc-linux-gnu"));
+        assert!(text.contains("Mapping Cancer Markers & friends"));
+    }
+
+    #[test]
+    fn already_escaped_reply_is_unchanged() {
+        let fixture = include_bytes!("fixtures/scheduler_reply_escaped.xml");
+        let sanitized = sanitize_xml(fixture);
+        assert_eq!(sanitized.as_bytes(), fixture);
+        let text = read_all_text(&sanitized);
+        assert!(text.contains("a < b & c > d \"e\" 'f'"));
+        assert!(text.contains("Ünïcode"));
+    }
+
+    #[test]
+    fn control_characters_and_latin1_in_reply() {
+        let fixture = include_bytes!("fixtures/scheduler_reply_latin1_control.xml");
+        let sanitized = sanitize_xml(fixture);
+        assert!(!sanitized.contains('\u{0}'));
+        assert!(!sanitized.contains('\u{1b}'));
+        let text = read_all_text(&sanitized);
+        assert!(text.contains("Universit\u{FFFD} de test"));
+        assert!(text.contains("line one\nline two"));
+    }
+
+    #[test]
+    fn cdata_and_comments_are_kept() {
+        let source = "<a><!-- a & b --><![CDATA[x & y < z]]>&</a>";
+        assert_eq!(
+            sanitize_xml(source.as_bytes()),
+            "<a><!-- a & b --><![CDATA[x & y < z]]>&amp;</a>"
+        );
+    }
+
+    #[test]
+    fn entities() {
+        assert_eq!(
+            sanitize_xml(b"&amp;&lt;&gt;&quot;&apos;"),
+            "&amp;&lt;&gt;&quot;&apos;"
+        );
+        assert_eq!(sanitize_xml(b"&#233;&#xE9;"), "&#233;&#xE9;");
+        assert_eq!(
+            sanitize_xml(b"&nbsp; &#1; &#xZZ; &"),
+            "&amp;nbsp; &amp;#1; &amp;#xZZ; &amp;"
+        );
+        assert_eq!(sanitize_xml(b"&amp"), "&amp;amp");
+    }
+}
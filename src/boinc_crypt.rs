@@ -0,0 +1,242 @@
+//! RSA keys and signatures in the text format of BOINC’s `crypt_prog`.
+//!
+//! A key file is the number of bits of the modulus on the first line, followed by the RSAREF
+//! structure (every number big-endian and left-padded with 0 to a fixed size) as hexadecimal, 32
+//! bytes per line, ending with a line containing only `.`. Signatures use the same hexadecimal
+//! format. BOINC sign the md5 of the text as 32 lowercase hex characters, with PKCS #1 v1.5
+//! padding but without the DigestInfo prefix.
+
+use anyhow::{bail, Context};
+use rsa::{
+    traits::{PrivateKeyParts, PublicKeyParts},
+    BigUint, Pkcs1v15Sign, RsaPrivateKey, RsaPublicKey,
+};
+
+/// RSAREF’s MAX_RSA_MODULUS_BITS. The BOINC client can’t read longer keys.
+pub const KEY_BITS: usize = 1024;
+const MAX_RSA_MODULUS_LEN: usize = KEY_BITS / 8;
+const MAX_RSA_PRIME_LEN: usize = MAX_RSA_MODULUS_LEN / 2;
+/// size of R_RSA_PUBLIC_KEY, minus the bits field
+const PUBLIC_KEY_DATA_LEN: usize = MAX_RSA_MODULUS_LEN * 2;
+/// size of R_RSA_PRIVATE_KEY, minus the bits field
+const PRIVATE_KEY_DATA_LEN: usize = MAX_RSA_MODULUS_LEN * 3 + MAX_RSA_PRIME_LEN * 5;
+
+pub fn generate_private_key() -> anyhow::Result<RsaPrivateKey> {
+    RsaPrivateKey::new(&mut rand::thread_rng(), KEY_BITS).context("Generating the RSA key")
+}
+
+fn format_hex_data(data: &[u8]) -> String {
+    let mut result = String::with_capacity(data.len() * 2 + data.len() / 32 + 3);
+    for line in data.chunks(32) {
+        for byte in line {
+            result.push_str(&format!("{:02x}", byte));
+        }
+        result.push('\n');
+    }
+    result.push_str(".\n");
+    result
+}
+
+/// Read hexadecimal data until the `.` terminator, ignoring whitespace
+fn parse_hex_data(text: &str) -> anyhow::Result<Vec<u8>> {
+    let mut digits = Vec::new();
+    for c in text.chars() {
+        if c == '.' {
+            break;
+        } else if let Some(digit) = c.to_digit(16) {
+            digits.push(digit as u8);
+        } else if !c.is_whitespace() {
+            bail!("Unexpected character “{}” in hexadecimal data", c);
+        }
+    }
+    if digits.len() % 2 != 0 {
+        bail!("Odd number of hexadecimal digits");
+    }
+    Ok(digits
+        .chunks(2)
+        .map(|pair| (pair[0] << 4) | pair[1])
+        .collect())
+}
+
+fn push_number(data: &mut Vec<u8>, number: &BigUint, len: usize) -> anyhow::Result<()> {
+    let bytes = number.to_bytes_be();
+    if bytes.len() > len {
+        bail!("Number too large for the BOINC key format");
+    }
+    data.resize(data.len() + len - bytes.len(), 0);
+    data.extend_from_slice(&bytes);
+    Ok(())
+}
+
+fn format_key(bits: usize, data: &[u8]) -> String {
+    format!("{}\n{}", bits, format_hex_data(data))
+}
+
+fn parse_key(text: &str, expected_len: usize) -> anyhow::Result<Vec<u8>> {
+    let (bits, data) = text
+        .trim_start()
+        .split_once('\n')
+        .context("Truncated key")?;
+    bits.trim()
+        .parse::<usize>()
+        .context("Parsing the key size")?;
+    let data = parse_hex_data(data)?;
+    if data.len() != expected_len {
+        bail!(
+            "The key contains {} bytes, {} were expected",
+            data.len(),
+            expected_len
+        );
+    }
+    Ok(data)
+}
+
+pub fn format_public_key(key: &RsaPublicKey) -> anyhow::Result<String> {
+    let mut data = Vec::with_capacity(PUBLIC_KEY_DATA_LEN);
+    push_number(&mut data, key.n(), MAX_RSA_MODULUS_LEN)?;
+    push_number(&mut data, key.e(), MAX_RSA_MODULUS_LEN)?;
+    Ok(format_key(key.n().bits(), &data))
+}
+
+pub fn parse_public_key(text: &str) -> anyhow::Result<RsaPublicKey> {
+    let data = parse_key(text, PUBLIC_KEY_DATA_LEN)?;
+    let (n, e) = data.split_at(MAX_RSA_MODULUS_LEN);
+    RsaPublicKey::new(BigUint::from_bytes_be(n), BigUint::from_bytes_be(e))
+        .context("Invalid public key")
+}
+
+pub fn format_private_key(key: &RsaPrivateKey) -> anyhow::Result<String> {
+    let primes = key.primes();
+    if primes.len() != 2 {
+        bail!("The BOINC key format only support two primes");
+    }
+    let (dp, dq, coefficient) = match (key.dp(), key.dq(), key.crt_coefficient()) {
+        (Some(dp), Some(dq), Some(coefficient)) => (dp, dq, coefficient),
+        _ => bail!("The private key is missing its precomputed values"),
+    };
+    let mut data = Vec::with_capacity(PRIVATE_KEY_DATA_LEN);
+    push_number(&mut data, key.n(), MAX_RSA_MODULUS_LEN)?;
+    push_number(&mut data, key.e(), MAX_RSA_MODULUS_LEN)?;
+    push_number(&mut data, key.d(), MAX_RSA_MODULUS_LEN)?;
+    push_number(&mut data, &primes[0], MAX_RSA_PRIME_LEN)?;
+    push_number(&mut data, &primes[1], MAX_RSA_PRIME_LEN)?;
+    push_number(&mut data, dp, MAX_RSA_PRIME_LEN)?;
+    push_number(&mut data, dq, MAX_RSA_PRIME_LEN)?;
+    push_number(&mut data, &coefficient, MAX_RSA_PRIME_LEN)?;
+    Ok(format_key(key.n().bits(), &data))
+}
+
+pub fn parse_private_key(text: &str) -> anyhow::Result<RsaPrivateKey> {
+    let data = parse_key(text, PRIVATE_KEY_DATA_LEN)?;
+    let mut numbers = Vec::new();
+    let mut remaining = &data[..];
+    for len in [MAX_RSA_MODULUS_LEN; 3]
+        .into_iter()
+        .chain([MAX_RSA_PRIME_LEN; 2])
+    {
+        let (number, rest) = remaining.split_at(len);
+        numbers.push(BigUint::from_bytes_be(number));
+        remaining = rest;
+    }
+    let primes = numbers.split_off(3);
+    let mut key = RsaPrivateKey::from_components(
+        numbers[0].clone(),
+        numbers[1].clone(),
+        numbers[2].clone(),
+        primes,
+    )
+    .context("Invalid private key")?;
+    key.precompute().context("Invalid private key")?;
+    Ok(key)
+}
+
+/// Sign text like `crypt_prog -sign_string`
+pub fn sign_string(text: &str, key: &RsaPrivateKey) -> anyhow::Result<String> {
+    let md5 = format!("{:x}", md5::compute(text));
+    let signature = key
+        .sign(Pkcs1v15Sign::new_unprefixed(), md5.as_bytes())
+        .context("Signing the text")?;
+    Ok(format_hex_data(&signature))
+}
+
+/// Check a signature made by sign_string, like the BOINC client does for the project urls
+pub fn verify_string_signature(text: &str, signature: &str, key: &RsaPublicKey) -> bool {
+    let signature = match parse_hex_data(signature) {
+        Ok(signature) => signature,
+        Err(_) => return false,
+    };
+    let md5 = format!("{:x}", md5::compute(text));
+    key.verify(Pkcs1v15Sign::new_unprefixed(), md5.as_bytes(), &signature)
+        .is_ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A key only used by these tests, and the signature of URL made with it by OpenSSL’s raw
+    /// PKCS #1 v1.5 signing of the md5, the same primitive `crypt_prog -sign_string` uses
+    const PRIVATE_KEY: &str = include_str!("fixtures/test_private_key");
+    const PUBLIC_KEY: &str = include_str!("fixtures/test_public_key");
+    const URL: &str = "https://boinc.example.com/proxy/asteroids/";
+    const URL_SIGNATURE: &str = include_str!("fixtures/test_url_signature");
+
+    #[test]
+    fn key_round_trip() {
+        let key = generate_private_key().unwrap();
+        let private_text = format_private_key(&key).unwrap();
+        assert!(private_text.starts_with("1024\n"));
+        assert!(private_text.ends_with("\n.\n"));
+        assert_eq!(parse_private_key(&private_text).unwrap(), key);
+
+        let public_key = key.to_public_key();
+        let public_text = format_public_key(&public_key).unwrap();
+        assert_eq!(parse_public_key(&public_text).unwrap(), public_key);
+    }
+
+    #[test]
+    fn fixture_keys_are_read_and_written_back_unchanged() {
+        let key = parse_private_key(PRIVATE_KEY).unwrap();
+        assert_eq!(format_private_key(&key).unwrap(), PRIVATE_KEY);
+        let public_key = parse_public_key(PUBLIC_KEY).unwrap();
+        assert_eq!(public_key, key.to_public_key());
+        assert_eq!(format_public_key(&public_key).unwrap(), PUBLIC_KEY);
+    }
+
+    #[test]
+    fn sign_and_verify() {
+        let key = generate_private_key().unwrap();
+        let public_key = key.to_public_key();
+        let signature = sign_string(URL, &key).unwrap();
+        assert!(verify_string_signature(URL, &signature, &public_key));
+        assert!(!verify_string_signature(
+            "https://boinc.example.com/proxy/other/",
+            &signature,
+            &public_key
+        ));
+        assert!(!verify_string_signature(
+            URL,
+            "not hexadecimal",
+            &public_key
+        ));
+        let other_key = generate_private_key().unwrap().to_public_key();
+        assert!(!verify_string_signature(URL, &signature, &other_key));
+    }
+
+    #[test]
+    fn matches_the_reference_signature() {
+        let key = parse_private_key(PRIVATE_KEY).unwrap();
+        // PKCS #1 v1.5 signatures are deterministic
+        assert_eq!(sign_string(URL, &key).unwrap(), URL_SIGNATURE);
+        let public_key = parse_public_key(PUBLIC_KEY).unwrap();
+        assert!(verify_string_signature(URL, URL_SIGNATURE, &public_key));
+    }
+
+    #[test]
+    fn invalid_keys() {
+        assert!(parse_public_key("").is_err());
+        assert!(parse_public_key("1024\n0011\n.\n").is_err());
+        assert!(parse_public_key("1024\n0g\n.\n").is_err());
+        assert!(parse_private_key(PUBLIC_KEY).is_err());
+    }
+}
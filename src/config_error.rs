@@ -0,0 +1,116 @@
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+
+/// A single problem found while loading config.json
+#[derive(Debug)]
+pub enum ConfigProblem {
+    InvalidJson(serde_json::Error),
+    /// A file referenced by the config can’t be read. project is None for global files.
+    UnreadableFile {
+        path: PathBuf,
+        project: Option<String>,
+        error: io::Error,
+    },
+    InvalidSigningKey {
+        path: PathBuf,
+        error: anyhow::Error,
+    },
+    EmptyField {
+        project: String,
+        field: &'static str,
+    },
+    /// Several projects end up with the same proxy url, usually a project id repeated in the
+    /// config
+    DuplicateProxyUrl {
+        url: String,
+        count: usize,
+    },
+    InvalidUrlSignature {
+        project: String,
+        url: String,
+    },
+    InvalidEligibilityRule {
+        project: String,
+        error: String,
+    },
+    /// index is the position of the planner in the planners list
+    InvalidPlanner {
+        index: usize,
+        error: String,
+    },
+}
+
+impl fmt::Display for ConfigProblem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidJson(err) => write!(f, "can’t parse the config: {}", err),
+            Self::UnreadableFile {
+                path,
+                project: Some(project),
+                error,
+            } => write!(
+                f,
+                "project “{}”: can’t read {}: {}",
+                project,
+                path.display(),
+                error
+            ),
+            Self::UnreadableFile {
+                path,
+                project: None,
+                error,
+            } => write!(f, "can’t read {}: {}", path.display(), error),
+            Self::InvalidSigningKey { path, error } => {
+                write!(f, "invalid signing key {}: {:#}", path.display(), error)
+            }
+            Self::EmptyField { project, field } => {
+                write!(f, "project “{}”: {} is empty", project, field)
+            }
+            Self::DuplicateProxyUrl { url, count } => {
+                write!(f, "{} projects have the proxy url {}", count, url)
+            }
+            Self::InvalidUrlSignature { project, url } => write!(
+                f,
+                "project “{}”: the url signature doesn’t match {} under the signing key",
+                project, url
+            ),
+            Self::InvalidEligibilityRule { project, error } => {
+                write!(
+                    f,
+                    "project “{}”: invalid eligibility rule: {}",
+                    project, error
+                )
+            }
+            Self::InvalidPlanner { index, error } => {
+                write!(f, "planner {}: {}", index, error)
+            }
+        }
+    }
+}
+
+/// Every problem found in config.json
+#[derive(Debug)]
+pub struct ConfigError {
+    pub problems: Vec<ConfigProblem>,
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} problem(s) in the config:", self.problems.len())?;
+        for problem in &self.problems {
+            write!(f, "\n- {}", problem)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+impl From<ConfigProblem> for ConfigError {
+    fn from(problem: ConfigProblem) -> Self {
+        ConfigError {
+            problems: vec![problem],
+        }
+    }
+}
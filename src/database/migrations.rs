@@ -0,0 +1,430 @@
+use anyhow::{bail, Context};
+use log::info;
+use rusqlite::{Connection, Transaction};
+
+type Migration = fn(&Transaction) -> anyhow::Result<()>;
+
+/// Each step upgrade the database from the version equal to its index to the next one.
+/// The version is stored in `PRAGMA user_version`. Never modify a step that has already been
+/// released, append a new one instead.
+const MIGRATIONS: &[(&str, Migration)] = &[
+    ("create the workunit and app_version tables", migrate_v1),
+    ("create the user table", migrate_v2),
+    ("create the host table", migrate_v3),
+    ("create the proxy_parse_failure table", migrate_v4),
+    ("create the project_exclusion table", migrate_v5),
+    (
+        "link hosts to users and create the user_keyword table",
+        migrate_v6,
+    ),
+    ("add the reported outcome of the results", migrate_v7),
+    ("split the results from the workunit table", migrate_v8),
+    ("add the app version metadata and their files", migrate_v9),
+];
+
+/// The version of the schema this binary write
+pub const SCHEMA_VERSION: u32 = MIGRATIONS.len() as u32;
+
+pub fn get_schema_version(conn: &Connection) -> anyhow::Result<u32> {
+    Ok(conn.query_row("PRAGMA user_version", [], |row| row.get(0))?)
+}
+
+/// Apply every missing migration, each in its own transaction
+pub fn upgrade(conn: &mut Connection) -> anyhow::Result<()> {
+    let version = get_schema_version(conn).context("Reading the schema version")?;
+    if version > SCHEMA_VERSION {
+        bail!(
+            "The database schema is at version {}, but this binary only support up to version {}",
+            version,
+            SCHEMA_VERSION
+        );
+    }
+    for (index, (description, migration)) in MIGRATIONS.iter().enumerate().skip(version as usize) {
+        let target_version = index as u32 + 1;
+        info!(
+            "migrating the database to version {}: {}",
+            target_version, description
+        );
+        let transaction = conn.transaction()?;
+        migration(&transaction).with_context(|| {
+            format!(
+                "Migrating the database to version {} ({})",
+                target_version, description
+            )
+        })?;
+        transaction.pragma_update(None, "user_version", target_version)?;
+        transaction.commit()?;
+    }
+    Ok(())
+}
+
+/// Version 0 is either an empty database, or the unversioned layout that already contains
+/// those two tables
+fn migrate_v1(transaction: &Transaction) -> anyhow::Result<()> {
+    transaction.execute_batch(
+        "CREATE TABLE IF NOT EXISTS workunit (
+            cpid TEXT,
+            result_name TEXT,
+            name TEXT,
+            project TEXT,
+            status NUMBER,
+            app_name TEXT,
+            rsc_fpops_est NUMBER,
+            rsc_fpops_bound NUMBER,
+            rsc_memory_bound NUMBER,
+            rsc_disk_bound NUMBER,
+            platform TEXT,
+            version_num NUMBER,
+            plan_class TEXT,
+            timestamp NUMBER,
+            PRIMARY KEY(result_name, project)
+        );
+        CREATE TABLE IF NOT EXISTS app_version (
+            project TEXT,
+            app_name TEXT,
+            user_friendly_name TEXT,
+            version NUMBER,
+            platform TEXT,
+            plan_class TEXT,
+            PRIMARY KEY(project, app_name, version, platform, plan_class)
+        );",
+    )?;
+    Ok(())
+}
+
+fn migrate_v2(transaction: &Transaction) -> anyhow::Result<()> {
+    transaction.execute_batch(
+        "CREATE TABLE IF NOT EXISTS user (
+            name TEXT PRIMARY KEY,
+            password_hash TEXT
+        );",
+    )?;
+    Ok(())
+}
+
+fn migrate_v3(transaction: &Transaction) -> anyhow::Result<()> {
+    transaction.execute_batch(
+        "CREATE TABLE IF NOT EXISTS host (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            cpid TEXT UNIQUE NOT NULL,
+            domain_name TEXT,
+            os_name TEXT,
+            os_version TEXT,
+            p_ncpus NUMBER,
+            p_vendor TEXT,
+            p_model TEXT,
+            p_fpops NUMBER,
+            m_nbytes NUMBER,
+            m_swap NUMBER,
+            d_total NUMBER,
+            d_free NUMBER,
+            coprocs TEXT,
+            platforms TEXT,
+            first_seen NUMBER,
+            last_seen NUMBER
+        );
+        ALTER TABLE workunit ADD COLUMN host_id INTEGER REFERENCES host(id);",
+    )?;
+    Ok(())
+}
+
+fn migrate_v4(transaction: &Transaction) -> anyhow::Result<()> {
+    transaction.execute_batch(
+        "CREATE TABLE proxy_parse_failure (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            project TEXT,
+            timestamp NUMBER,
+            error TEXT,
+            body_length NUMBER,
+            body_sample TEXT
+        );",
+    )?;
+    Ok(())
+}
+
+fn migrate_v5(transaction: &Transaction) -> anyhow::Result<()> {
+    transaction.execute_batch(
+        "CREATE TABLE project_exclusion (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            host_id INTEGER NOT NULL REFERENCES host(id),
+            project TEXT NOT NULL,
+            started NUMBER,
+            until NUMBER,
+            failed_results NUMBER,
+            finished_results NUMBER,
+            share_factor NUMBER
+        );
+        CREATE INDEX project_exclusion_host_project ON project_exclusion(host_id, project);",
+    )?;
+    Ok(())
+}
+
+fn migrate_v6(transaction: &Transaction) -> anyhow::Result<()> {
+    transaction.execute_batch(
+        "ALTER TABLE host ADD COLUMN user_name TEXT REFERENCES user(name);
+        CREATE TABLE user_keyword (
+            user_name TEXT NOT NULL REFERENCES user(name),
+            keyword TEXT NOT NULL,
+            preference TEXT NOT NULL,
+            PRIMARY KEY(user_name, keyword)
+        );",
+    )?;
+    Ok(())
+}
+
+fn migrate_v7(transaction: &Transaction) -> anyhow::Result<()> {
+    transaction.execute_batch(
+        "ALTER TABLE workunit ADD COLUMN final_cpu_time NUMBER;
+        ALTER TABLE workunit ADD COLUMN final_elapsed_time NUMBER;
+        ALTER TABLE workunit ADD COLUMN exit_status NUMBER;
+        ALTER TABLE workunit ADD COLUMN fpops_cumulative NUMBER;
+        ALTER TABLE workunit ADD COLUMN reported_time NUMBER;",
+    )?;
+    Ok(())
+}
+
+/// Until now each row was a result with a copy of its work unit, so every result recorded so far
+/// came with its work unit
+/// The results are indexed for the queries of a host, a project or a time range, ordered by
+/// the time they were sent
+fn migrate_v8(transaction: &Transaction) -> anyhow::Result<()> {
+    transaction.execute_batch(
+        "CREATE TABLE result (
+            project TEXT,
+            result_name TEXT,
+            workunit_name TEXT,
+            cpid TEXT,
+            host_id INTEGER REFERENCES host(id),
+            status NUMBER,
+            platform TEXT,
+            version_num NUMBER,
+            plan_class TEXT,
+            timestamp NUMBER,
+            final_cpu_time NUMBER,
+            final_elapsed_time NUMBER,
+            exit_status NUMBER,
+            fpops_cumulative NUMBER,
+            reported_time NUMBER,
+            missing_workunit NUMBER NOT NULL DEFAULT 0,
+            PRIMARY KEY(result_name, project)
+        );
+        INSERT INTO result
+            SELECT project, result_name, name, cpid, host_id, status, platform, version_num, plan_class, timestamp, final_cpu_time, final_elapsed_time, exit_status, fpops_cumulative, reported_time, 0
+            FROM workunit;
+        CREATE TABLE new_workunit (
+            project TEXT,
+            name TEXT,
+            app_name TEXT,
+            rsc_fpops_est NUMBER,
+            rsc_fpops_bound NUMBER,
+            rsc_memory_bound NUMBER,
+            rsc_disk_bound NUMBER,
+            PRIMARY KEY(project, name)
+        );
+        INSERT OR IGNORE INTO new_workunit
+            SELECT project, name, app_name, rsc_fpops_est, rsc_fpops_bound, rsc_memory_bound, rsc_disk_bound
+            FROM workunit ORDER BY timestamp;
+        DROP TABLE workunit;
+        ALTER TABLE new_workunit RENAME TO workunit;
+        CREATE INDEX result_cpid_timestamp ON result(cpid, timestamp);
+        CREATE INDEX result_host_id_timestamp ON result(host_id, timestamp);
+        CREATE INDEX result_project_timestamp ON result(project, timestamp);
+        CREATE INDEX result_timestamp ON result(timestamp);",
+    )?;
+    Ok(())
+}
+
+/// The CPUs and speed of an app version are estimated for each host, they are stored with its
+/// tasks
+fn migrate_v9(transaction: &Transaction) -> anyhow::Result<()> {
+    transaction.execute_batch(
+        "ALTER TABLE result ADD COLUMN avg_ncpus NUMBER;
+        ALTER TABLE result ADD COLUMN flops NUMBER;
+        ALTER TABLE app_version ADD COLUMN coproc_type TEXT;
+        ALTER TABLE app_version ADD COLUMN coproc_count NUMBER;
+        ALTER TABLE app_version ADD COLUMN gpu_ram NUMBER;
+        CREATE TABLE file_info (
+            project TEXT,
+            name TEXT,
+            nbytes NUMBER,
+            md5_cksum TEXT,
+            PRIMARY KEY(project, name)
+        );
+        CREATE TABLE app_version_file (
+            project TEXT,
+            app_name TEXT,
+            version NUMBER,
+            platform TEXT,
+            plan_class TEXT,
+            file_name TEXT,
+            open_name TEXT,
+            main_program NUMBER,
+            PRIMARY KEY(project, app_name, version, platform, plan_class, file_name)
+        );",
+    )?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The layout created by the unversioned code, with only two tables
+    const V0_LAYOUT: &str = "CREATE TABLE workunit (
+            cpid TEXT,
+            result_name TEXT,
+            name TEXT,
+            project TEXT,
+            status NUMBER,
+            app_name TEXT,
+            rsc_fpops_est NUMBER,
+            rsc_fpops_bound NUMBER,
+            rsc_memory_bound NUMBER,
+            rsc_disk_bound NUMBER,
+            platform TEXT,
+            version_num NUMBER,
+            plan_class TEXT,
+            timestamp NUMBER,
+            PRIMARY KEY(result_name, project)
+        );
+        CREATE TABLE app_version (
+            project TEXT,
+            app_name TEXT,
+            user_friendly_name TEXT,
+            version NUMBER,
+            platform TEXT,
+            plan_class TEXT,
+            PRIMARY KEY(project, app_name, version, platform, plan_class)
+        );
+        INSERT INTO workunit VALUES ('cpid', 'wu_0', 'wu', 'project', 1, 'app', 1e12, 1e13, 1e9, 1e9, 'x86_64-pc-linux-gnu', 100, '', 1000);
+        INSERT INTO app_version VALUES ('project', 'app', 'An app', 100, 'x86_64-pc-linux-gnu', '');";
+
+    /// Security: table_name and column_name should be trusted input
+    fn check_column_exist(
+        conn: &Connection,
+        table_name: &str,
+        column_name: &str,
+    ) -> anyhow::Result<bool> {
+        Ok(conn
+            .prepare(&format!(
+                "SELECT name FROM pragma_table_info('{}') WHERE name='{}'",
+                table_name, column_name
+            ))?
+            .query([])?
+            .next()?
+            .is_some())
+    }
+
+    fn check_table_exist(conn: &Connection, table_name: &str) -> bool {
+        conn.query_row(
+            "SELECT count(*) FROM sqlite_master WHERE type='table' AND name=?1",
+            [table_name],
+            |row| row.get::<_, u32>(0),
+        )
+        .unwrap()
+            == 1
+    }
+
+    #[test]
+    fn upgrade_v0_database() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(V0_LAYOUT).unwrap();
+        assert_eq!(get_schema_version(&conn).unwrap(), 0);
+
+        upgrade(&mut conn).unwrap();
+
+        assert_eq!(get_schema_version(&conn).unwrap(), SCHEMA_VERSION);
+        assert!(check_table_exist(&conn, "user"));
+        assert!(check_table_exist(&conn, "host"));
+        assert!(check_table_exist(&conn, "proxy_parse_failure"));
+        assert!(check_table_exist(&conn, "project_exclusion"));
+        assert!(check_table_exist(&conn, "user_keyword"));
+        assert!(check_column_exist(&conn, "host", "user_name").unwrap());
+        assert!(check_table_exist(&conn, "result"));
+        assert!(check_column_exist(&conn, "result", "fpops_cumulative").unwrap());
+        assert!(check_column_exist(&conn, "result", "host_id").unwrap());
+        let missing_workunit: bool = conn
+            .query_row("SELECT missing_workunit FROM result", [], |row| row.get(0))
+            .unwrap();
+        assert!(!missing_workunit);
+        let result_index_count: u32 = conn
+            .query_row(
+                "SELECT count(*) FROM sqlite_master WHERE type='index' AND tbl_name='result' AND name LIKE 'result_%'",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(result_index_count, 4);
+        let (result_name, workunit_name, host_id): (String, String, Option<i64>) = conn
+            .query_row(
+                "SELECT result_name, workunit_name, host_id FROM result",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+            )
+            .unwrap();
+        assert_eq!(result_name, "wu_0");
+        assert_eq!(workunit_name, "wu");
+        assert_eq!(host_id, None);
+        let app_name: String = conn
+            .query_row(
+                "SELECT app_name FROM workunit WHERE project='project' AND name='wu'",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(app_name, "app");
+        assert!(check_column_exist(&conn, "app_version", "coproc_type").unwrap());
+        assert!(!check_column_exist(&conn, "app_version", "flops").unwrap());
+        assert!(check_column_exist(&conn, "result", "flops").unwrap());
+        assert!(check_table_exist(&conn, "file_info"));
+        assert!(check_table_exist(&conn, "app_version_file"));
+        let app_version_count: u32 = conn
+            .query_row("SELECT count(*) FROM app_version", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(app_version_count, 1);
+    }
+
+    #[test]
+    fn finished_v0_results_are_not_in_progress() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("v0.sqlite");
+        let conn = Connection::open(&path).unwrap();
+        conn.execute_batch(V0_LAYOUT).unwrap();
+        // their status was updated, but the time they were reported wasn’t stored yet
+        conn.execute_batch(
+            "INSERT INTO workunit VALUES ('cpid', 'wu_1', 'wu', 'project', 6, 'app', 1e12, 1e13, 1e9, 1e9, 'x86_64-pc-linux-gnu', 100, '', 1000);
+            INSERT INTO workunit VALUES ('cpid', 'wu_2', 'wu', 'project', 3, 'app', 1e12, 1e13, 1e9, 1e9, 'x86_64-pc-linux-gnu', 100, '', 1000);
+            INSERT INTO workunit VALUES ('cpid', 'wu_3', 'wu', 'project', 5, 'app', 1e12, 1e13, 1e9, 1e9, 'x86_64-pc-linux-gnu', 100, '', 1000);",
+        )
+        .unwrap();
+        drop(conn);
+
+        let database = crate::DataBase::new(&path).unwrap();
+        let delivered = database.sum_delivered_fpops("cpid", 0, 1000, 0.0).unwrap();
+        // wu_0 in progress and wu_3 uploaded, the aborted and errored ones count nothing
+        assert_eq!(delivered["project"], 2e12);
+    }
+
+    #[test]
+    fn upgrade_empty_database() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        upgrade(&mut conn).unwrap();
+        assert_eq!(get_schema_version(&conn).unwrap(), SCHEMA_VERSION);
+        assert!(check_table_exist(&conn, "workunit"));
+        assert!(check_table_exist(&conn, "result"));
+        assert!(check_table_exist(&conn, "app_version"));
+
+        // running it again is a no-op
+        upgrade(&mut conn).unwrap();
+        assert_eq!(get_schema_version(&conn).unwrap(), SCHEMA_VERSION);
+    }
+
+    #[test]
+    fn refuse_newer_database() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        conn.pragma_update(None, "user_version", SCHEMA_VERSION + 1)
+            .unwrap();
+        assert!(upgrade(&mut conn).is_err());
+        assert!(!check_table_exist(&conn, "workunit"));
+    }
+}
@@ -0,0 +1,835 @@
+use std::{
+    collections::HashMap,
+    fmt,
+    path::Path,
+    str::FromStr,
+    sync::{Arc, Mutex, MutexGuard},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{bail, Context};
+use rusqlite::{functions::FunctionFlags, Connection};
+
+mod migrations;
+
+mod pool;
+use pool::{ReadConnection, ReaderPool, BUSY_TIMEOUT};
+
+mod writes;
+
+mod task_query;
+pub use task_query::{TaskIter, TaskQuery};
+
+use crate::{
+    device_info::{Coprocs, HostInfo},
+    DeviceInfo, ResultState,
+};
+
+pub fn unix_timestamp() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+/// The host independent metadata of an app version, its CPU and speed estimates are those of
+/// each Task
+#[derive(Debug, Clone)]
+pub struct AppVersion {
+    pub project: String,
+    pub app_name: String,
+    pub user_friendly_name: String,
+    pub version: u64,
+    pub platform: String,
+    pub plan_class: String,
+    /// the GPU or other coprocessor used, None for a CPU app version
+    pub coproc_type: Option<String>,
+    /// coprocessors used by a task, can be a fraction
+    pub coproc_count: f64,
+    /// GPU memory used, in bytes
+    pub gpu_ram: f64,
+    pub files: Vec<AppVersionFile>,
+}
+
+/// A file of an app version, the `<file_ref>` merged with its `<file_info>`
+#[derive(Debug, Clone)]
+pub struct AppVersionFile {
+    pub name: String,
+    /// the name the application opens it as, empty if it is the file name
+    pub open_name: String,
+    pub main_program: bool,
+    /// size and checksum, None if the reply didn’t have the `<file_info>`
+    pub nbytes: Option<f64>,
+    pub md5_cksum: Option<String>,
+}
+
+/// A job of a project, sent to hosts as one or several tasks
+#[derive(Debug, Clone)]
+pub struct WorkUnit {
+    pub project: String,
+    pub name: String,
+    pub app_name: String,
+    pub rsc_fpops_est: f64,
+    pub rsc_fpops_bound: f64,
+    pub rsc_memory_bound: f64,
+    pub rsc_disk_bound: f64,
+}
+
+/// A copy of a work unit sent to a host, what BOINC calls a result, stored in the result table
+#[derive(Debug, Clone)]
+pub struct Task {
+    pub project: String,
+    pub result_name: String,
+    pub workunit_name: String,
+    pub cpid: String,
+    /// id of the row in the host table
+    pub host_id: Option<i64>,
+    pub status: ResultState,
+    pub platform: String,
+    pub version_num: u64,
+    pub plan_class: String,
+    pub timestamp: u64,
+    /// sent without its work unit, which the host already had. It may still be known from
+    /// another host.
+    pub missing_workunit: bool,
+    /// CPUs used, as estimated by the project for the host. None if the reply didn’t have the
+    /// app version of the task.
+    pub avg_ncpus: Option<f64>,
+    /// estimated speed on the host, in FLOPS
+    pub flops: Option<f64>,
+    /// The rest is only set once the host reported the result
+    pub final_cpu_time: Option<f64>,
+    pub final_elapsed_time: Option<f64>,
+    pub exit_status: Option<i32>,
+    /// FLOPs counted by the application, few of them report it
+    pub fpops_cumulative: Option<f64>,
+    pub reported_time: Option<u64>,
+}
+
+/// A `<result>` reported by the host in a scheduler request
+#[derive(Debug)]
+pub struct ResultReport {
+    pub project: String,
+    pub result_name: String,
+    pub status: ResultState,
+    pub final_cpu_time: f64,
+    pub final_elapsed_time: f64,
+    pub exit_status: i32,
+    pub fpops_cumulative: Option<f64>,
+    pub reported_time: u64,
+}
+
+#[derive(Debug)]
+pub struct Host {
+    pub id: i64,
+    pub host_info: HostInfo,
+    pub platforms: Vec<String>,
+    pub first_seen: u64,
+    pub last_seen: u64,
+}
+
+/// Everything learned from one exchange between a host and a project scheduler, recorded at once
+/// by DataBase::record_exchange
+pub struct SchedulerExchange {
+    /// id of the host, recorded before contacting the project
+    pub host_id: i64,
+    pub timestamp: u64,
+    /// the results reported in the request
+    pub reports: Vec<ResultReport>,
+    /// the work units sent in the reply
+    pub workunits: Vec<WorkUnit>,
+    /// the tasks sent in the reply, their host_id is replaced by the one of the host
+    pub tasks: Vec<Task>,
+    pub app_versions: Vec<AppVersion>,
+    pub parse_failure: Option<ProxyParseFailure>,
+}
+
+/// A scheduler reply that couldn’t be analysed, but was still forwarded to the client
+pub struct ProxyParseFailure {
+    pub project: String,
+    pub timestamp: u64,
+    pub error: String,
+    /// length of the full body, in bytes
+    pub body_length: u64,
+    /// the start of the body
+    pub body_sample: String,
+}
+
+impl ProxyParseFailure {
+    /// Maximum number of bytes kept in body_sample
+    pub const SAMPLE_LENGTH: usize = 4096;
+
+    pub fn new(project: &str, timestamp: u64, error: String, body: &str) -> Self {
+        let mut sample_end = body.len().min(Self::SAMPLE_LENGTH);
+        while !body.is_char_boundary(sample_end) {
+            sample_end -= 1;
+        }
+        ProxyParseFailure {
+            project: project.to_string(),
+            timestamp,
+            error,
+            body_length: body.len() as u64,
+            body_sample: body[..sample_end].to_string(),
+        }
+    }
+}
+
+/// A project taken away from a host because too many of its results failed there
+#[derive(Debug)]
+pub struct ProjectExclusion {
+    pub host_id: i64,
+    pub project: String,
+    pub started: u64,
+    /// end of the cool-down
+    pub until: u64,
+    /// errored or aborted results in the window that triggered the exclusion
+    pub failed_results: u64,
+    /// results that reached a final state in that window
+    pub finished_results: u64,
+    /// the priority was multiplied by this, None if the project was detached
+    pub share_factor: Option<f64>,
+}
+
+pub struct User {
+    pub name: String,
+    /// The hash sent by the BOINC client, that is md5(password + lowercase(name))
+    pub password_hash: String,
+}
+
+impl User {
+    pub fn from_password(name: &str, password: &str) -> Self {
+        User {
+            name: name.to_string(),
+            password_hash: format!(
+                "{:x}",
+                md5::compute(format!("{}{}", password, name.to_lowercase()))
+            ),
+        }
+    }
+
+    pub fn check_password_hash(&self, password_hash: &str) -> bool {
+        self.password_hash.eq_ignore_ascii_case(password_hash)
+    }
+}
+
+/// What a user think of a project keyword, like a science area. No preference is “maybe”.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeywordPreference {
+    Yes,
+    No,
+}
+
+impl fmt::Display for KeywordPreference {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Yes => "yes",
+            Self::No => "no",
+        })
+    }
+}
+
+impl FromStr for KeywordPreference {
+    type Err = anyhow::Error;
+
+    fn from_str(text: &str) -> anyhow::Result<Self> {
+        match text {
+            "yes" => Ok(Self::Yes),
+            "no" => Ok(Self::No),
+            _ => bail!("Unknown keyword preference “{}”", text),
+        }
+    }
+}
+
+/// Register the SQL functions the queries need. The bundled SQLite is built without its math
+/// functions.
+fn add_functions(conn: &Connection) -> rusqlite::Result<()> {
+    conn.create_scalar_function(
+        "exp",
+        1,
+        FunctionFlags::SQLITE_UTF8 | FunctionFlags::SQLITE_DETERMINISTIC,
+        |context| Ok(context.get::<f64>(0)?.exp()),
+    )
+}
+
+/// Run blocking database work on the thread pool of actix, so the async workers stay free to
+/// handle other requests
+pub async fn run_blocking<F, R>(work: F) -> anyhow::Result<R>
+where
+    F: FnOnce() -> anyhow::Result<R> + Send + 'static,
+    R: Send + 'static,
+{
+    actix_web::web::block(work)
+        .await
+        .context("Running a blocking task")?
+}
+
+/// The database, in WAL mode. Every write goes through a single connection, while reads use a
+/// pool of read-only connections, so they don’t wait for the writes. The methods block, use
+/// run_blocking from async code.
+#[derive(Clone)]
+pub struct DataBase {
+    writer: Arc<Mutex<Connection>>,
+    readers: Arc<ReaderPool>,
+}
+
+impl DataBase {
+    pub fn new(db_path: &Path) -> anyhow::Result<Self> {
+        let mut conn = Connection::open(db_path).context("Opening the sqlite database")?;
+        conn.busy_timeout(BUSY_TIMEOUT)?;
+        conn.pragma_update(None, "journal_mode", "WAL")
+            .context("Enabling the WAL mode")?;
+        // durable enough in WAL mode, a crash can only lose the last transactions
+        conn.pragma_update(None, "synchronous", "NORMAL")?;
+        add_functions(&conn)?;
+        migrations::upgrade(&mut conn).context("upgrading/seeding the database")?;
+        Ok(DataBase {
+            writer: Arc::new(Mutex::new(conn)),
+            readers: Arc::new(ReaderPool::new(db_path.to_path_buf())),
+        })
+    }
+
+    /// A temporary database, that only live as long as this DataBase and its clones
+    pub fn new_in_memory() -> anyhow::Result<Self> {
+        let (mut conn, readers) = ReaderPool::in_memory()?;
+        add_functions(&conn)?;
+        migrations::upgrade(&mut conn).context("seeding the database")?;
+        Ok(DataBase {
+            writer: Arc::new(Mutex::new(conn)),
+            readers: Arc::new(readers),
+        })
+    }
+
+    fn writer(&self) -> MutexGuard<'_, Connection> {
+        self.writer.lock().unwrap()
+    }
+
+    fn reader(&self) -> anyhow::Result<ReadConnection<'_>> {
+        self.readers.get()
+    }
+
+    pub fn add_app_version(&self, app_version: &AppVersion) -> anyhow::Result<()> {
+        writes::add_app_version(&self.writer(), app_version)
+    }
+
+    pub fn add_user(&self, user: &User) -> anyhow::Result<()> {
+        let conn = self.writer();
+        conn.prepare_cached("INSERT OR REPLACE INTO user VALUES (?1, ?2)")?
+            .execute((&user.name, &user.password_hash))?;
+        Ok(())
+    }
+
+    pub fn get_user(&self, name: &str) -> anyhow::Result<Option<User>> {
+        let conn = self.reader()?;
+        let mut statement =
+            conn.prepare_cached("SELECT name, password_hash FROM user WHERE name=?1")?;
+        let mut rows = statement.query([name])?;
+        Ok(match rows.next()? {
+            Some(row) => Some(User {
+                name: row.get(0)?,
+                password_hash: row.get(1)?,
+            }),
+            None => None,
+        })
+    }
+
+    /// Insert or refresh the host, and return its id
+    pub fn upsert_host(&self, device_info: &DeviceInfo, timestamp: u64) -> anyhow::Result<i64> {
+        writes::upsert_host(&self.writer(), device_info, timestamp)
+    }
+
+    /// Remember which user attached the host, to apply their preferences
+    pub fn set_host_user(&self, host_id: i64, user_name: &str) -> anyhow::Result<()> {
+        let conn = self.writer();
+        conn.prepare_cached("UPDATE host SET user_name=?1 WHERE id=?2")?
+            .execute((user_name, host_id))?;
+        Ok(())
+    }
+
+    /// Set the preference of a user for a keyword, None removing it
+    pub fn set_keyword_preference(
+        &self,
+        user_name: &str,
+        keyword: &str,
+        preference: Option<KeywordPreference>,
+    ) -> anyhow::Result<()> {
+        let conn = self.writer();
+        match preference {
+            Some(preference) => conn
+                .prepare_cached("INSERT OR REPLACE INTO user_keyword VALUES (?1, ?2, ?3)")?
+                .execute((user_name, keyword, preference.to_string()))?,
+            None => conn
+                .prepare_cached("DELETE FROM user_keyword WHERE user_name=?1 AND keyword=?2")?
+                .execute((user_name, keyword))?,
+        };
+        Ok(())
+    }
+
+    /// The keyword preferences of the user who attached the host, empty if it is unknown
+    pub fn get_host_keyword_preferences(
+        &self,
+        cpid: &str,
+    ) -> anyhow::Result<HashMap<String, KeywordPreference>> {
+        let conn = self.reader()?;
+        let mut statement = conn.prepare_cached(
+            "SELECT keyword, preference FROM user_keyword
+            JOIN host ON host.user_name = user_keyword.user_name
+            WHERE host.cpid=?1",
+        )?;
+        let mut rows = statement.query([cpid])?;
+        let mut preferences = HashMap::new();
+        while let Some(row) = rows.next()? {
+            let preference: String = row.get(1)?;
+            preferences.insert(row.get(0)?, preference.parse()?);
+        }
+        Ok(preferences)
+    }
+
+    pub fn get_host_id(&self, cpid: &str) -> anyhow::Result<Option<i64>> {
+        let conn = self.reader()?;
+        let mut statement = conn.prepare_cached("SELECT id FROM host WHERE cpid=?1")?;
+        let mut rows = statement.query([cpid])?;
+        Ok(match rows.next()? {
+            Some(row) => Some(row.get(0)?),
+            None => None,
+        })
+    }
+
+    pub fn get_host(&self, id: i64) -> anyhow::Result<Option<Host>> {
+        let conn = self.reader()?;
+        let mut statement = conn.prepare_cached("SELECT id, cpid, domain_name, os_name, os_version, p_ncpus, p_vendor, p_model, p_fpops, m_nbytes, m_swap, d_total, d_free, coprocs, platforms, first_seen, last_seen FROM host WHERE id=?1")?;
+        let mut rows = statement.query([id])?;
+        let row = match rows.next()? {
+            Some(row) => row,
+            None => return Ok(None),
+        };
+        let coprocs: String = row.get(13)?;
+        let platforms: String = row.get(14)?;
+        Ok(Some(Host {
+            id: row.get(0)?,
+            host_info: HostInfo {
+                host_cpid: row.get(1)?,
+                domain_name: row.get(2)?,
+                os_name: row.get(3)?,
+                os_version: row.get(4)?,
+                p_ncpus: row.get(5)?,
+                p_vendor: row.get(6)?,
+                p_model: row.get(7)?,
+                p_fpops: row.get(8)?,
+                m_nbytes: row.get(9)?,
+                m_swap: row.get(10)?,
+                d_total: row.get(11)?,
+                d_free: row.get(12)?,
+                coprocs: serde_json::from_str::<Coprocs>(&coprocs)?,
+            },
+            platforms: serde_json::from_str(&platforms)?,
+            first_seen: row.get(15)?,
+            last_seen: row.get(16)?,
+        }))
+    }
+
+    pub fn add_work_unit(&self, workunit: &WorkUnit) -> anyhow::Result<()> {
+        writes::add_work_unit(&self.writer(), workunit)
+    }
+
+    pub fn add_task(&self, task: &Task) -> anyhow::Result<()> {
+        writes::add_task(&self.writer(), task)
+    }
+
+    pub fn add_proxy_parse_failure(&self, failure: &ProxyParseFailure) -> anyhow::Result<()> {
+        writes::add_proxy_parse_failure(&self.writer(), failure)
+    }
+
+    pub fn add_project_exclusion(&self, exclusion: &ProjectExclusion) -> anyhow::Result<()> {
+        let conn = self.writer();
+        conn.prepare_cached("INSERT INTO project_exclusion (host_id, project, started, until, failed_results, finished_results, share_factor) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)")?
+            .execute((
+                exclusion.host_id,
+                &exclusion.project,
+                exclusion.started,
+                exclusion.until,
+                exclusion.failed_results,
+                exclusion.finished_results,
+                exclusion.share_factor,
+            ))?;
+        Ok(())
+    }
+
+    /// The most recent exclusion of the project on this host, whether its cool-down is over or not
+    pub fn get_last_project_exclusion(
+        &self,
+        host_id: i64,
+        project: &str,
+    ) -> anyhow::Result<Option<ProjectExclusion>> {
+        let conn = self.reader()?;
+        let mut statement = conn.prepare_cached("SELECT host_id, project, started, until, failed_results, finished_results, share_factor FROM project_exclusion WHERE host_id=?1 AND project=?2 ORDER BY until DESC LIMIT 1")?;
+        let mut rows = statement.query((host_id, project))?;
+        Ok(match rows.next()? {
+            Some(row) => Some(ProjectExclusion {
+                host_id: row.get(0)?,
+                project: row.get(1)?,
+                started: row.get(2)?,
+                until: row.get(3)?,
+                failed_results: row.get(4)?,
+                finished_results: row.get(5)?,
+                share_factor: row.get(6)?,
+            }),
+            None => None,
+        })
+    }
+
+    /// Count the failed and the finished results of a project sent to the host after timestamp,
+    /// as defined by ResultState::FAILED and ResultState::FINISHED
+    pub fn count_result_outcomes(
+        &self,
+        cpid: &str,
+        project: &str,
+        timestamp: u64,
+    ) -> anyhow::Result<(u64, u64)> {
+        let conn = self.reader()?;
+        let counts = conn
+            .prepare_cached(&format!(
+                "SELECT coalesce(sum(status IN ({})), 0), coalesce(sum(status IN ({})), 0)
+                FROM result WHERE cpid=?1 AND project=?2 AND timestamp > ?3",
+                ResultState::sql_list(&ResultState::FAILED),
+                ResultState::sql_list(&ResultState::FINISHED),
+            ))?
+            .query_row(rusqlite::params![cpid, project, timestamp], |row| {
+                Ok((row.get(0)?, row.get(1)?))
+            })?;
+        Ok(counts)
+    }
+
+    /// Sum, per project, the FLOPs delivered by the host in the work sent after since. A task
+    /// in progress count its estimation, one uploaded without error its counted FLOPs (or the
+    /// estimation), the others nothing. The tasks uploaded before their outcome was stored have
+    /// no exit status, they count as without error. A task whose work unit was never recorded
+    /// has no estimation, so it only counts once it reported its FLOPs. Each task is weighted by
+    /// exp(-decay_rate * age), its age being the seconds between its sending and now.
+    pub fn sum_delivered_fpops(
+        &self,
+        cpid: &str,
+        since: u64,
+        now: u64,
+        decay_rate: f64,
+    ) -> anyhow::Result<HashMap<String, f64>> {
+        let conn = self.reader()?;
+        let mut statement = conn.prepare_cached(&format!(
+            "SELECT result.project, sum(
+                CASE
+                    WHEN status IN ({}) THEN coalesce(rsc_fpops_est, 0)
+                    WHEN status = ?4 AND coalesce(exit_status, 0) = 0 THEN coalesce(fpops_cumulative, rsc_fpops_est, 0)
+                    ELSE 0
+                END * exp(-?5 * (?3 - timestamp)))
+            FROM result LEFT JOIN workunit ON workunit.project = result.project AND workunit.name = result.workunit_name
+            WHERE cpid=?1 AND timestamp > ?2
+            GROUP BY result.project",
+            ResultState::sql_list(&ResultState::IN_PROGRESS),
+        ))?;
+        let rows = statement.query_map(
+            rusqlite::params![cpid, since, now, ResultState::FilesUploaded, decay_rate],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )?;
+        Ok(rows.collect::<Result<_, _>>()?)
+    }
+
+    pub fn report_result(&self, report: &ResultReport) -> anyhow::Result<()> {
+        writes::report_result(&self.writer(), report)
+    }
+
+    /// Record a scheduler exchange in a single transaction: either all of it is saved, or nothing
+    pub fn record_exchange(&self, exchange: &SchedulerExchange) -> anyhow::Result<()> {
+        let mut conn = self.writer();
+        let transaction = conn.transaction()?;
+        for report in &exchange.reports {
+            writes::report_result(&transaction, report)?;
+        }
+        for workunit in &exchange.workunits {
+            writes::add_work_unit(&transaction, workunit)?;
+        }
+        for task in &exchange.tasks {
+            writes::add_task(
+                &transaction,
+                &Task {
+                    host_id: Some(exchange.host_id),
+                    ..task.clone()
+                },
+            )?;
+        }
+        for app_version in &exchange.app_versions {
+            writes::add_app_version(&transaction, app_version)?;
+        }
+        if let Some(parse_failure) = &exchange.parse_failure {
+            writes::add_proxy_parse_failure(&transaction, parse_failure)?;
+        }
+        transaction.commit()?;
+        Ok(())
+    }
+
+    /// The tasks matching the query with their work unit, read lazily
+    pub fn query_tasks(&self, query: TaskQuery) -> TaskIter {
+        TaskIter::new(self.clone(), query)
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    pub(crate) fn workunit(project: &str, name: &str) -> WorkUnit {
+        WorkUnit {
+            project: project.to_string(),
+            name: name.to_string(),
+            app_name: "app".to_string(),
+            rsc_fpops_est: 1e12,
+            rsc_fpops_bound: 1e13,
+            rsc_memory_bound: 1e9,
+            rsc_disk_bound: 1e9,
+        }
+    }
+
+    /// The host “cpid”, extra being more elements of `<host_info>`
+    pub(crate) fn host_info(os_name: &str, os_version: &str, extra: &str) -> HostInfo {
+        quick_xml::de::from_str(&format!(
+            "<host_info><os_name>{}</os_name><os_version>{}</os_version><host_cpid>cpid</host_cpid>{}</host_info>",
+            os_name, os_version, extra
+        ))
+        .unwrap()
+    }
+
+    /// A task of the work unit “wu”
+    pub(crate) fn task(project: &str, result_name: &str, timestamp: u64) -> Task {
+        Task {
+            project: project.to_string(),
+            result_name: result_name.to_string(),
+            workunit_name: "wu".to_string(),
+            cpid: "cpid".to_string(),
+            host_id: None,
+            status: ResultState::FilesDownloading,
+            platform: "x86_64-pc-linux-gnu".to_string(),
+            version_num: 100,
+            plan_class: String::new(),
+            timestamp,
+            missing_workunit: false,
+            avg_ncpus: None,
+            flops: None,
+            final_cpu_time: None,
+            final_elapsed_time: None,
+            exit_status: None,
+            fpops_cumulative: None,
+            reported_time: None,
+        }
+    }
+
+    #[test]
+    fn sum_delivered_fpops() {
+        let database = DataBase::new_in_memory().unwrap();
+        let now = 1_000_000;
+        for project in ["a", "b"] {
+            database.add_work_unit(&workunit(project, "wu")).unwrap();
+        }
+        for (project, result_name, timestamp) in [
+            ("a", "in_progress", now),
+            ("a", "uploaded", now),
+            ("a", "too_old", now - 500),
+            ("b", "failed", now),
+            ("b", "half_life_ago", now - 100),
+        ] {
+            database
+                .add_task(&task(project, result_name, timestamp))
+                .unwrap();
+        }
+        // its FLOPs can’t be estimated without the work unit
+        let mut orphan = task("b", "orphan", now);
+        orphan.workunit_name = "missing".to_string();
+        database.add_task(&orphan).unwrap();
+        for (project, result_name, status, exit_status, fpops_cumulative) in [
+            ("a", "uploaded", ResultState::FilesUploaded, 0, Some(3e12)),
+            ("b", "failed", ResultState::ComputeError, 1, None),
+            ("b", "half_life_ago", ResultState::FilesUploaded, 0, None),
+        ] {
+            database
+                .report_result(&ResultReport {
+                    project: project.to_string(),
+                    result_name: result_name.to_string(),
+                    status,
+                    final_cpu_time: 10.0,
+                    final_elapsed_time: 10.0,
+                    exit_status,
+                    fpops_cumulative,
+                    reported_time: now,
+                })
+                .unwrap();
+        }
+
+        let delivered = database
+            .sum_delivered_fpops("cpid", now - 200, now, 0.0)
+            .unwrap();
+        assert_eq!(delivered["a"], 4e12);
+        assert_eq!(delivered["b"], 1e12);
+
+        let delivered = database
+            .sum_delivered_fpops("cpid", now - 200, now, std::f64::consts::LN_2 / 100.0)
+            .unwrap();
+        assert_eq!(delivered["a"], 4e12);
+        assert!((delivered["b"] - 0.5e12).abs() < 1.0);
+    }
+
+    fn exchange(database_broken: bool) -> (DataBase, i64, anyhow::Result<()>) {
+        let database = DataBase::new_in_memory().unwrap();
+        if database_broken {
+            database
+                .writer()
+                .execute_batch("DROP TABLE app_version")
+                .unwrap();
+        }
+        let host_id = database
+            .upsert_host(
+                &DeviceInfo::new(host_info("Linux", "6.1", ""), "x86_64-pc-linux-gnu", &[]),
+                1000,
+            )
+            .unwrap();
+        let result = database.record_exchange(&SchedulerExchange {
+            host_id,
+            timestamp: 1000,
+            reports: Vec::new(),
+            workunits: vec![workunit("project", "wu")],
+            tasks: vec![
+                Task {
+                    avg_ncpus: Some(0.5),
+                    flops: Some(1e11),
+                    ..task("project", "wu_0", 1000)
+                },
+                task("project", "wu_1", 1000),
+            ],
+            app_versions: vec![AppVersion {
+                project: "project".to_string(),
+                app_name: "app".to_string(),
+                user_friendly_name: "An app".to_string(),
+                version: 100,
+                platform: "x86_64-pc-linux-gnu".to_string(),
+                plan_class: "cuda".to_string(),
+                coproc_type: Some("NVIDIA".to_string()),
+                coproc_count: 1.0,
+                gpu_ram: 1e9,
+                files: vec![
+                    AppVersionFile {
+                        name: "app_100_cuda".to_string(),
+                        open_name: String::new(),
+                        main_program: true,
+                        nbytes: Some(1e6),
+                        md5_cksum: Some("d41d8cd98f00b204e9800998ecf8427e".to_string()),
+                    },
+                    AppVersionFile {
+                        name: "missing_info".to_string(),
+                        open_name: "lib.so".to_string(),
+                        main_program: false,
+                        nbytes: None,
+                        md5_cksum: None,
+                    },
+                ],
+            }],
+            parse_failure: None,
+        });
+        (database, host_id, result)
+    }
+
+    #[test]
+    fn record_exchange() {
+        let (database, host_id, result) = exchange(false);
+        result.unwrap();
+        let tasks: Vec<(Task, Option<WorkUnit>)> = database
+            .query_tasks(TaskQuery::new())
+            .map(|row| row.unwrap())
+            .collect();
+        assert_eq!(tasks.len(), 2);
+        assert_eq!(
+            (tasks[0].0.avg_ncpus, tasks[0].0.flops),
+            (Some(0.5), Some(1e11))
+        );
+        assert_eq!((tasks[1].0.avg_ncpus, tasks[1].0.flops), (None, None));
+        for (task, workunit) in &tasks {
+            assert_eq!(task.host_id, Some(host_id));
+            assert_eq!(workunit.as_ref().unwrap().name, "wu");
+        }
+        let conn = database.reader().unwrap();
+        let coproc_type: String = conn
+            .query_row("SELECT coproc_type FROM app_version", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(coproc_type, "NVIDIA");
+        let main_program: String = conn
+            .query_row(
+                "SELECT file_name FROM app_version_file WHERE main_program",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(main_program, "app_100_cuda");
+        let nbytes: f64 = conn
+            .query_row(
+                "SELECT nbytes FROM file_info JOIN app_version_file ON file_info.name = file_name",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(nbytes, 1e6);
+    }
+
+    #[test]
+    fn record_exchange_is_atomic() {
+        let (database, _, result) = exchange(true);
+        assert!(result.is_err());
+        assert_eq!(database.query_tasks(TaskQuery::new()).count(), 0);
+    }
+
+    #[test]
+    fn file_database_reads_through_the_pool() {
+        let dir = tempfile::tempdir().unwrap();
+        let database = DataBase::new(&dir.path().join("test.sqlite")).unwrap();
+        let journal_mode: String = database
+            .writer()
+            .query_row("PRAGMA journal_mode", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(journal_mode, "wal");
+
+        database
+            .add_user(&User::from_password("Bob", "password"))
+            .unwrap();
+        // hold a reader while another one is opened, then reuse them
+        {
+            let _reader = database.reader().unwrap();
+            assert!(database.get_user("Bob").unwrap().is_some());
+        }
+        assert!(database.get_user("Bob").unwrap().is_some());
+        assert!(database
+            .reader()
+            .unwrap()
+            .execute("DELETE FROM user", [])
+            .is_err());
+    }
+
+    #[test]
+    fn in_memory_database_writes_while_reading() {
+        let database = DataBase::new_in_memory().unwrap();
+        let other = DataBase::new_in_memory().unwrap();
+        database
+            .add_user(&User::from_password("Bob", "password"))
+            .unwrap();
+        {
+            let reader = database.reader().unwrap();
+            let mut statement = reader.prepare("SELECT name FROM user").unwrap();
+            let mut rows = statement.query([]).unwrap();
+            assert!(rows.next().unwrap().is_some());
+            // the reader is still in the middle of its query
+            database
+                .add_user(&User::from_password("Alice", "password"))
+                .unwrap();
+            assert!(database.get_user("Alice").unwrap().is_some());
+        }
+        assert!(database
+            .reader()
+            .unwrap()
+            .execute("DELETE FROM user", [])
+            .is_err());
+        assert!(other.get_user("Bob").unwrap().is_none());
+    }
+}
@@ -0,0 +1,163 @@
+use std::{
+    ops::Deref,
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Condvar, Mutex,
+    },
+    time::Duration,
+};
+
+use anyhow::{bail, Context};
+use rusqlite::{Connection, OpenFlags};
+
+use super::add_functions;
+
+/// How long a connection wait for a lock held by another one, like the add-user command
+/// writing while the server run
+pub const BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Idle read-only connections kept open. More are opened when needed.
+const MAX_IDLE_READERS: usize = 8;
+
+/// Read-only connections open at once, past that a reader wait for one to be given back
+const MAX_READERS: usize = 32;
+
+/// The in-memory databases created by this process, to give each one a distinct name
+static IN_MEMORY_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+struct PoolState {
+    idle: Vec<Connection>,
+    /// idle and in use connections
+    open: usize,
+}
+
+/// Read-only connections to a database in WAL mode, so they don’t wait for the writer
+pub struct ReaderPool {
+    path: PathBuf,
+    in_memory: bool,
+    state: Mutex<PoolState>,
+    given_back: Condvar,
+}
+
+impl ReaderPool {
+    pub fn new(path: PathBuf) -> Self {
+        ReaderPool {
+            path,
+            in_memory: false,
+            state: Mutex::new(PoolState {
+                idle: Vec::new(),
+                open: 0,
+            }),
+            given_back: Condvar::new(),
+        }
+    }
+
+    /// Open a new in-memory database, shared by the returned writer and the pool readers. It lives
+    /// as long as the writer.
+    pub fn in_memory() -> anyhow::Result<(Connection, Self)> {
+        let path = format!(
+            "file:boinc-account-manager-{}-{}?mode=memory&cache=shared",
+            std::process::id(),
+            IN_MEMORY_COUNT.fetch_add(1, Ordering::Relaxed)
+        );
+        let writer = Connection::open_with_flags(
+            &path,
+            OpenFlags::SQLITE_OPEN_READ_WRITE
+                | OpenFlags::SQLITE_OPEN_CREATE
+                | OpenFlags::SQLITE_OPEN_URI
+                | OpenFlags::SQLITE_OPEN_NO_MUTEX,
+        )
+        .context("Opening the sqlite database")?;
+        let pool = ReaderPool {
+            in_memory: true,
+            ..Self::new(path.into())
+        };
+        Ok((writer, pool))
+    }
+
+    fn open(&self) -> anyhow::Result<Connection> {
+        let conn = if self.in_memory {
+            let conn = Connection::open_with_flags(
+                &self.path,
+                OpenFlags::SQLITE_OPEN_READ_WRITE
+                    | OpenFlags::SQLITE_OPEN_URI
+                    | OpenFlags::SQLITE_OPEN_NO_MUTEX,
+            )
+            .context("Opening a connection to the in-memory sqlite database")?;
+            conn.pragma_update(None, "query_only", true)?;
+            // the shared cache locks whole tables: without this a write would fail while a
+            // reader is open, instead of waiting like in WAL mode
+            conn.pragma_update(None, "read_uncommitted", true)?;
+            conn
+        } else {
+            Connection::open_with_flags(
+                &self.path,
+                OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_NO_MUTEX,
+            )
+            .context("Opening a read-only connection to the sqlite database")?
+        };
+        conn.busy_timeout(BUSY_TIMEOUT)?;
+        add_functions(&conn)?;
+        Ok(conn)
+    }
+
+    pub fn get(&self) -> anyhow::Result<ReadConnection<'_>> {
+        let mut state = self.state.lock().unwrap();
+        loop {
+            if let Some(conn) = state.idle.pop() {
+                return Ok(ReadConnection {
+                    conn: Some(conn),
+                    pool: self,
+                });
+            }
+            if state.open < MAX_READERS {
+                state.open += 1;
+                break;
+            }
+            let (new_state, timeout) = self.given_back.wait_timeout(state, BUSY_TIMEOUT).unwrap();
+            state = new_state;
+            if timeout.timed_out() && state.idle.is_empty() && state.open >= MAX_READERS {
+                bail!("All the {} database readers are in use", MAX_READERS);
+            }
+        }
+        drop(state);
+        match self.open() {
+            Ok(conn) => Ok(ReadConnection {
+                conn: Some(conn),
+                pool: self,
+            }),
+            Err(err) => {
+                self.state.lock().unwrap().open -= 1;
+                self.given_back.notify_one();
+                Err(err)
+            }
+        }
+    }
+}
+
+/// A connection for read-only queries, given back to its pool when dropped
+pub struct ReadConnection<'a> {
+    conn: Option<Connection>,
+    pool: &'a ReaderPool,
+}
+
+impl Deref for ReadConnection<'_> {
+    type Target = Connection;
+
+    fn deref(&self) -> &Connection {
+        self.conn.as_ref().unwrap()
+    }
+}
+
+impl Drop for ReadConnection<'_> {
+    fn drop(&mut self) {
+        let mut state = self.pool.state.lock().unwrap();
+        if state.idle.len() < MAX_IDLE_READERS {
+            state.idle.push(self.conn.take().unwrap());
+        } else {
+            state.open -= 1;
+        }
+        self.pool.given_back.notify_one();
+    }
+}
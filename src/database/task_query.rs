@@ -0,0 +1,422 @@
+use std::collections::VecDeque;
+
+use rusqlite::{types::Value, Row};
+
+use crate::{DataBase, ResultState};
+
+use super::{Task, WorkUnit};
+
+const TASK_COLUMNS: &str = "result.project, result_name, workunit_name, cpid, host_id, status, platform, version_num, plan_class, timestamp, final_cpu_time, final_elapsed_time, exit_status, fpops_cumulative, reported_time, missing_workunit, avg_ncpus, flops, workunit.name, app_name, rsc_fpops_est, rsc_fpops_bound, rsc_memory_bound, rsc_disk_bound, result.rowid";
+
+/// Rows read from the database at once
+const PAGE_SIZE: u64 = 500;
+
+/// A task and its work unit, None if it was sent without it
+fn task_from_row(row: &Row) -> rusqlite::Result<(Task, Option<WorkUnit>)> {
+    let task = Task {
+        project: row.get(0)?,
+        result_name: row.get(1)?,
+        workunit_name: row.get(2)?,
+        cpid: row.get(3)?,
+        host_id: row.get(4)?,
+        status: row.get(5)?,
+        platform: row.get(6)?,
+        version_num: row.get(7)?,
+        plan_class: row.get(8)?,
+        timestamp: row.get(9)?,
+        missing_workunit: row.get(15)?,
+        avg_ncpus: row.get(16)?,
+        flops: row.get(17)?,
+        final_cpu_time: row.get(10)?,
+        final_elapsed_time: row.get(11)?,
+        exit_status: row.get(12)?,
+        fpops_cumulative: row.get(13)?,
+        reported_time: row.get(14)?,
+    };
+    let workunit = match row.get::<_, Option<String>>(18)? {
+        Some(name) => Some(WorkUnit {
+            project: task.project.clone(),
+            name,
+            app_name: row.get(19)?,
+            rsc_fpops_est: row.get(20)?,
+            rsc_fpops_bound: row.get(21)?,
+            rsc_memory_bound: row.get(22)?,
+            rsc_disk_bound: row.get(23)?,
+        }),
+        None => None,
+    };
+    Ok((task, workunit))
+}
+
+/// Filters on the tasks and their work units, for DataBase::query_tasks. Every filter set must
+/// match. The tasks come ordered by the time they were sent.
+#[derive(Debug, Clone)]
+pub struct TaskQuery {
+    host_id: Option<i64>,
+    cpid: Option<String>,
+    project: Option<String>,
+    app_name: Option<String>,
+    statuses: Option<Vec<ResultState>>,
+    sent_after: Option<u64>,
+    sent_before: Option<u64>,
+    offset: u64,
+    limit: Option<u64>,
+    page_size: u64,
+}
+
+impl Default for TaskQuery {
+    fn default() -> Self {
+        TaskQuery {
+            host_id: None,
+            cpid: None,
+            project: None,
+            app_name: None,
+            statuses: None,
+            sent_after: None,
+            sent_before: None,
+            offset: 0,
+            limit: None,
+            page_size: PAGE_SIZE,
+        }
+    }
+}
+
+impl TaskQuery {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn host_id(mut self, host_id: i64) -> Self {
+        self.host_id = Some(host_id);
+        self
+    }
+
+    /// Unlike host_id, the cpid is also set on the tasks recorded before the host table existed
+    pub fn cpid(mut self, cpid: &str) -> Self {
+        self.cpid = Some(cpid.to_string());
+        self
+    }
+
+    pub fn project(mut self, project: &str) -> Self {
+        self.project = Some(project.to_string());
+        self
+    }
+
+    pub fn app_name(mut self, app_name: &str) -> Self {
+        self.app_name = Some(app_name.to_string());
+        self
+    }
+
+    /// Only keep the tasks in one of those states
+    pub fn statuses(mut self, statuses: impl IntoIterator<Item = ResultState>) -> Self {
+        self.statuses = Some(statuses.into_iter().collect());
+        self
+    }
+
+    /// Only keep the tasks sent strictly after this timestamp
+    pub fn sent_after(mut self, timestamp: u64) -> Self {
+        self.sent_after = Some(timestamp);
+        self
+    }
+
+    /// Only keep the tasks sent strictly before this timestamp
+    pub fn sent_before(mut self, timestamp: u64) -> Self {
+        self.sent_before = Some(timestamp);
+        self
+    }
+
+    /// Skip this many tasks
+    pub fn offset(mut self, offset: u64) -> Self {
+        self.offset = offset;
+        self
+    }
+
+    /// Return at most this many tasks
+    pub fn limit(mut self, limit: u64) -> Self {
+        self.limit = Some(limit);
+        self
+    }
+
+    /// The WHERE clause and its parameters
+    fn conditions(&self) -> (Vec<String>, Vec<Value>) {
+        let mut conditions = Vec::new();
+        let mut params = Vec::new();
+        let mut add = |condition: &str, value: Value| {
+            params.push(value);
+            conditions.push(condition.replace('?', &format!("?{}", params.len())));
+        };
+        if let Some(host_id) = self.host_id {
+            add("result.host_id = ?", Value::Integer(host_id));
+        }
+        if let Some(cpid) = &self.cpid {
+            add("result.cpid = ?", Value::Text(cpid.clone()));
+        }
+        if let Some(project) = &self.project {
+            add("result.project = ?", Value::Text(project.clone()));
+        }
+        if let Some(app_name) = &self.app_name {
+            add("workunit.app_name = ?", Value::Text(app_name.clone()));
+        }
+        if let Some(sent_after) = self.sent_after {
+            add("result.timestamp > ?", Value::Integer(sent_after as i64));
+        }
+        if let Some(sent_before) = self.sent_before {
+            add("result.timestamp < ?", Value::Integer(sent_before as i64));
+        }
+        if let Some(statuses) = &self.statuses {
+            let mut placeholders = Vec::new();
+            for status in statuses {
+                params.push(Value::Integer(u32::from(*status).into()));
+                placeholders.push(format!("?{}", params.len()));
+            }
+            conditions.push(format!("result.status IN ({})", placeholders.join(", ")));
+        }
+        (conditions, params)
+    }
+}
+
+/// The tasks matching a TaskQuery, with their work unit. They are read a page at a time, so the database
+/// isn’t locked while iterating.
+pub struct TaskIter {
+    database: DataBase,
+    query: TaskQuery,
+    page: VecDeque<(Task, Option<WorkUnit>)>,
+    /// timestamp and rowid of the last task read
+    last: Option<(u64, i64)>,
+    remaining: Option<u64>,
+    done: bool,
+}
+
+impl TaskIter {
+    pub(super) fn new(database: DataBase, query: TaskQuery) -> Self {
+        TaskIter {
+            database,
+            remaining: query.limit,
+            query,
+            page: VecDeque::new(),
+            last: None,
+            done: false,
+        }
+    }
+
+    /// The SQL of the next page and its parameters
+    fn page_sql(&self, page_size: u64) -> (String, Vec<Value>) {
+        let (mut conditions, mut params) = self.query.conditions();
+        let offset = match self.last {
+            Some((timestamp, rowid)) => {
+                params.push(Value::Integer(timestamp as i64));
+                params.push(Value::Integer(rowid));
+                conditions.push(format!(
+                    "(result.timestamp, result.rowid) > (?{}, ?{})",
+                    params.len() - 1,
+                    params.len()
+                ));
+                0
+            }
+            None => self.query.offset,
+        };
+        let mut sql = format!(
+            "SELECT {} FROM result LEFT JOIN workunit ON workunit.project = result.project AND workunit.name = result.workunit_name",
+            TASK_COLUMNS
+        );
+        if !conditions.is_empty() {
+            sql.push_str(" WHERE ");
+            sql.push_str(&conditions.join(" AND "));
+        }
+        params.push(Value::Integer(page_size as i64));
+        params.push(Value::Integer(offset as i64));
+        sql.push_str(&format!(
+            " ORDER BY result.timestamp, result.rowid LIMIT ?{} OFFSET ?{}",
+            params.len() - 1,
+            params.len()
+        ));
+        (sql, params)
+    }
+
+    fn read_page(&mut self) -> anyhow::Result<()> {
+        let page_size = match self.remaining {
+            Some(remaining) => remaining.min(self.query.page_size),
+            None => self.query.page_size,
+        };
+        let (sql, params) = self.page_sql(page_size);
+        let conn = self.database.reader()?;
+        // the SQL changes with the filters and the page, so it isn't worth caching
+        let mut statement = conn.prepare(&sql)?;
+        let mut rows = statement.query(rusqlite::params_from_iter(params))?;
+        let mut count = 0;
+        while let Some(row) = rows.next()? {
+            let (task, workunit) = task_from_row(row)?;
+            self.last = Some((task.timestamp, row.get(24)?));
+            self.page.push_back((task, workunit));
+            count += 1;
+        }
+        if count < page_size {
+            self.done = true;
+        }
+        if let Some(remaining) = &mut self.remaining {
+            *remaining -= count;
+            if *remaining == 0 {
+                self.done = true;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Iterator for TaskIter {
+    type Item = anyhow::Result<(Task, Option<WorkUnit>)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.page.is_empty() && !self.done {
+            if let Err(err) = self.read_page() {
+                self.done = true;
+                return Some(Err(err));
+            }
+        }
+        self.page.pop_front().map(Ok)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::tests::{task, workunit};
+
+    fn names(database: &DataBase, query: TaskQuery) -> Vec<String> {
+        database
+            .query_tasks(query)
+            .map(|row| row.unwrap().0.result_name)
+            .collect()
+    }
+
+    #[test]
+    fn filters() {
+        let database = DataBase::new_in_memory().unwrap();
+        for project in ["a", "b"] {
+            database.add_work_unit(&workunit(project, "wu")).unwrap();
+        }
+        for (project, result_name, timestamp) in [
+            ("a", "a1", 10),
+            ("a", "a2", 20),
+            ("b", "b1", 15),
+            ("b", "b2", 30),
+        ] {
+            database
+                .add_task(&task(project, result_name, timestamp))
+                .unwrap();
+        }
+        let mut other_app = workunit("a", "other_wu");
+        other_app.app_name = "other".to_string();
+        database.add_work_unit(&other_app).unwrap();
+        let mut other_task = task("a", "a3", 25);
+        other_task.workunit_name = "other_wu".to_string();
+        other_task.status = ResultState::Aborted;
+        database.add_task(&other_task).unwrap();
+        // sent without its work unit
+        let mut orphan = task("b", "b3", 35);
+        orphan.workunit_name = "missing".to_string();
+        database.add_task(&orphan).unwrap();
+
+        assert_eq!(
+            names(&database, TaskQuery::new()),
+            ["a1", "b1", "a2", "a3", "b2", "b3"]
+        );
+        assert_eq!(
+            names(&database, TaskQuery::new().project("a").app_name("app")),
+            ["a1", "a2"]
+        );
+        assert_eq!(
+            names(&database, TaskQuery::new().sent_after(10).sent_before(30)),
+            ["b1", "a2", "a3"]
+        );
+        assert_eq!(
+            names(
+                &database,
+                TaskQuery::new().statuses([ResultState::Aborted, ResultState::ComputeError])
+            ),
+            ["a3"]
+        );
+        assert!(names(&database, TaskQuery::new().cpid("other")).is_empty());
+        assert!(names(&database, TaskQuery::new().statuses([])).is_empty());
+
+        let rows: Vec<(Task, Option<WorkUnit>)> = database
+            .query_tasks(TaskQuery::new().project("b"))
+            .map(|row| row.unwrap())
+            .collect();
+        assert_eq!(rows[0].1.as_ref().unwrap().app_name, "app");
+        assert!(rows[2].1.is_none());
+    }
+
+    /// Every filter on the result table is served by an index, already in the order of the tasks
+    #[test]
+    fn indexes() {
+        let database = DataBase::new_in_memory().unwrap();
+        for (query, searched) in [
+            (TaskQuery::new(), false),
+            (TaskQuery::new().host_id(1), true),
+            (TaskQuery::new().cpid("cpid"), true),
+            (
+                TaskQuery::new().cpid("cpid").project("a").sent_after(10),
+                true,
+            ),
+            (TaskQuery::new().project("a"), true),
+            (TaskQuery::new().sent_after(10), true),
+            (TaskQuery::new().sent_after(10).sent_before(20), true),
+            (
+                TaskQuery::new()
+                    .app_name("app")
+                    .statuses([ResultState::Aborted]),
+                false,
+            ),
+        ] {
+            let mut iter = TaskIter::new(database.clone(), query.clone());
+            for last in [None, Some((10, 1))] {
+                iter.last = last;
+                let (sql, params) = iter.page_sql(PAGE_SIZE);
+                let conn = database.reader().unwrap();
+                let mut statement = conn
+                    .prepare(&format!("EXPLAIN QUERY PLAN {}", sql))
+                    .unwrap();
+                let plan: Vec<String> = statement
+                    .query_map(rusqlite::params_from_iter(params), |row| row.get(3))
+                    .unwrap()
+                    .map(|detail| detail.unwrap())
+                    .collect();
+                // without a filter, the tasks are read in the order of the timestamp index
+                let used_index = if searched {
+                    plan[0].starts_with("SEARCH result USING INDEX result_")
+                } else {
+                    plan[0].contains("result USING INDEX result_")
+                };
+                assert!(used_index, "{:?}: {:?}", query, plan);
+                assert!(
+                    !plan.iter().any(|detail| detail.contains("TEMP B-TREE")),
+                    "{:?}: {:?}",
+                    query,
+                    plan
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn pagination() {
+        let database = DataBase::new_in_memory().unwrap();
+        // several tasks sent in the same second
+        for index in 0..10 {
+            database
+                .add_task(&task("a", &format!("r{}", index), index / 3))
+                .unwrap();
+        }
+        let mut query = TaskQuery::new();
+        query.page_size = 3;
+        let all: Vec<String> = (0..10).map(|index| format!("r{}", index)).collect();
+        assert_eq!(names(&database, query.clone()), all);
+        assert_eq!(
+            names(&database, query.clone().offset(2).limit(5)),
+            all[2..7]
+        );
+        assert_eq!(names(&database, query.clone().offset(8).limit(5)), all[8..]);
+        assert!(names(&database, query.limit(0)).is_empty());
+    }
+}
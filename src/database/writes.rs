@@ -0,0 +1,168 @@
+//! The statements shared by the single writes of DataBase and the transaction of
+//! DataBase::record_exchange
+
+use rusqlite::Connection;
+
+use super::{AppVersion, ProxyParseFailure, ResultReport, Task, WorkUnit};
+use crate::DeviceInfo;
+
+/// The app versions recorded before their metadata was kept get it the next time they are sent
+pub(super) fn add_app_version(conn: &Connection, app_version: &AppVersion) -> anyhow::Result<()> {
+    conn.prepare_cached(
+        "INSERT INTO app_version (project, app_name, user_friendly_name, version, platform, plan_class, coproc_type, coproc_count, gpu_ram)
+        VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)
+        ON CONFLICT(project, app_name, version, platform, plan_class) DO UPDATE SET
+            user_friendly_name=excluded.user_friendly_name,
+            coproc_type=excluded.coproc_type,
+            coproc_count=excluded.coproc_count,
+            gpu_ram=excluded.gpu_ram",
+    )?
+    .execute(rusqlite::params![
+        &app_version.project,
+        &app_version.app_name,
+        &app_version.user_friendly_name,
+        app_version.version,
+        &app_version.platform,
+        &app_version.plan_class,
+        &app_version.coproc_type,
+        app_version.coproc_count,
+        app_version.gpu_ram,
+    ])?;
+    for file in &app_version.files {
+        conn.prepare_cached("INSERT OR IGNORE INTO app_version_file (project, app_name, version, platform, plan_class, file_name, open_name, main_program) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)")?
+            .execute((
+                &app_version.project,
+                &app_version.app_name,
+                app_version.version,
+                &app_version.platform,
+                &app_version.plan_class,
+                &file.name,
+                &file.open_name,
+                file.main_program,
+            ))?;
+        if let (Some(nbytes), Some(md5_cksum)) = (file.nbytes, &file.md5_cksum) {
+            conn.prepare_cached(
+                "INSERT OR REPLACE INTO file_info (project, name, nbytes, md5_cksum) VALUES (?1, ?2, ?3, ?4)",
+            )?
+            .execute((&app_version.project, &file.name, nbytes, md5_cksum))?;
+        }
+    }
+    Ok(())
+}
+
+pub(super) fn upsert_host(
+    conn: &Connection,
+    device_info: &DeviceInfo,
+    timestamp: u64,
+) -> anyhow::Result<i64> {
+    let host_info = &device_info.host_info;
+    let coprocs = serde_json::to_string(&host_info.coprocs)?;
+    let platforms = serde_json::to_string(&device_info.platforms)?;
+    let id = conn
+        .prepare_cached(
+            "INSERT INTO host (cpid, domain_name, os_name, os_version, p_ncpus, p_vendor, p_model, p_fpops, m_nbytes, m_swap, d_total, d_free, coprocs, platforms, first_seen, last_seen)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?15)
+            ON CONFLICT(cpid) DO UPDATE SET
+                domain_name=excluded.domain_name,
+                os_name=excluded.os_name,
+                os_version=excluded.os_version,
+                p_ncpus=excluded.p_ncpus,
+                p_vendor=excluded.p_vendor,
+                p_model=excluded.p_model,
+                p_fpops=excluded.p_fpops,
+                m_nbytes=excluded.m_nbytes,
+                m_swap=excluded.m_swap,
+                d_total=excluded.d_total,
+                d_free=excluded.d_free,
+                coprocs=excluded.coprocs,
+                platforms=excluded.platforms,
+                last_seen=excluded.last_seen
+            RETURNING id",
+        )?
+        .query_row(
+            rusqlite::params![
+                &host_info.host_cpid,
+                &host_info.domain_name,
+                &host_info.os_name,
+                &host_info.os_version,
+                host_info.p_ncpus,
+                &host_info.p_vendor,
+                &host_info.p_model,
+                host_info.p_fpops,
+                host_info.m_nbytes,
+                host_info.m_swap,
+                host_info.d_total,
+                host_info.d_free,
+                coprocs,
+                platforms,
+                timestamp,
+            ],
+            |row| row.get(0),
+        )?;
+    Ok(id)
+}
+
+/// A work unit sent to several hosts is only stored once
+pub(super) fn add_work_unit(conn: &Connection, workunit: &WorkUnit) -> anyhow::Result<()> {
+    conn.prepare_cached("INSERT OR IGNORE INTO workunit (project, name, app_name, rsc_fpops_est, rsc_fpops_bound, rsc_memory_bound, rsc_disk_bound) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)")?
+        .execute((
+            &workunit.project,
+            &workunit.name,
+            &workunit.app_name,
+            workunit.rsc_fpops_est,
+            workunit.rsc_fpops_bound,
+            workunit.rsc_memory_bound,
+            workunit.rsc_disk_bound,
+        ))?;
+    Ok(())
+}
+
+pub(super) fn add_task(conn: &Connection, task: &Task) -> anyhow::Result<()> {
+    conn.prepare_cached("INSERT OR IGNORE INTO result (project, result_name, workunit_name, cpid, host_id, status, platform, version_num, plan_class, timestamp, missing_workunit, avg_ncpus, flops) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13)")?
+        .execute((
+            &task.project,
+            &task.result_name,
+            &task.workunit_name,
+            &task.cpid,
+            task.host_id,
+            task.status,
+            &task.platform,
+            task.version_num,
+            &task.plan_class,
+            task.timestamp,
+            task.missing_workunit,
+            task.avg_ncpus,
+            task.flops,
+        ))?;
+    Ok(())
+}
+
+pub(super) fn add_proxy_parse_failure(
+    conn: &Connection,
+    failure: &ProxyParseFailure,
+) -> anyhow::Result<()> {
+    conn.prepare_cached("INSERT INTO proxy_parse_failure (project, timestamp, error, body_length, body_sample) VALUES (?1, ?2, ?3, ?4, ?5)")?
+        .execute((
+            &failure.project,
+            failure.timestamp,
+            &failure.error,
+            failure.body_length,
+            &failure.body_sample,
+        ))?;
+    Ok(())
+}
+
+pub(super) fn report_result(conn: &Connection, report: &ResultReport) -> anyhow::Result<()> {
+    conn.prepare_cached("UPDATE result SET status=?1, final_cpu_time=?2, final_elapsed_time=?3, exit_status=?4, fpops_cumulative=?5, reported_time=?6 WHERE project=?7 AND result_name=?8")?
+        .execute((
+            report.status,
+            report.final_cpu_time,
+            report.final_elapsed_time,
+            report.exit_status,
+            report.fpops_cumulative,
+            report.reported_time,
+            &report.project,
+            &report.result_name,
+        ))?;
+    Ok(())
+}
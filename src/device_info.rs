@@ -1,12 +1,107 @@
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+pub struct Coproc {
+    /// only set for the generic `<coproc>` element, the vendor one are implied by their tag
+    #[serde(default, rename = "type")]
+    pub coproc_type: String,
+    #[serde(default)]
+    pub count: u32,
+    #[serde(default)]
+    pub name: String,
+    #[serde(default)]
+    pub peak_flops: f64,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+pub struct Coprocs {
+    #[serde(default)]
+    pub coproc_cuda: Option<Coproc>,
+    #[serde(default)]
+    pub coproc_ati: Option<Coproc>,
+    #[serde(default)]
+    pub coproc_intel_gpu: Option<Coproc>,
+    #[serde(default)]
+    pub coproc: Vec<Coproc>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
 pub struct HostInfo {
     pub os_name: String,
     pub os_version: String,
     pub host_cpid: String,
+    #[serde(default)]
+    pub domain_name: String,
+    /// number of CPU cores
+    #[serde(default)]
+    pub p_ncpus: u32,
+    #[serde(default)]
+    pub p_vendor: String,
+    #[serde(default)]
+    pub p_model: String,
+    /// per-core floating point benchmark, in FLOPS
+    #[serde(default)]
+    pub p_fpops: f64,
+    /// RAM, in bytes
+    #[serde(default)]
+    pub m_nbytes: f64,
+    #[serde(default)]
+    pub m_swap: f64,
+    /// disk size, in bytes
+    #[serde(default)]
+    pub d_total: f64,
+    #[serde(default)]
+    pub d_free: f64,
+    #[serde(default)]
+    pub coprocs: Coprocs,
+}
+
+impl HostInfo {
+    /// The lowercase vendors of the GPU and other coprocessors: nvidia, amd, intel, or the type of
+    /// a generic coprocessor
+    pub fn gpu_vendors(&self) -> Vec<String> {
+        let coprocs = &self.coprocs;
+        let mut vendors = Vec::new();
+        if coprocs.coproc_cuda.is_some() {
+            vendors.push("nvidia".to_string());
+        }
+        if coprocs.coproc_ati.is_some() {
+            vendors.push("amd".to_string());
+        }
+        if coprocs.coproc_intel_gpu.is_some() {
+            vendors.push("intel".to_string());
+        }
+        for coproc in &coprocs.coproc {
+            vendors.push(coproc.coproc_type.to_lowercase());
+        }
+        vendors
+    }
+}
+
+/// An `<alt_platform>` element, as sent in both the rpc and scheduler requests
+#[derive(Deserialize, Debug)]
+pub struct AltPlatform {
+    pub name: String,
 }
 
 pub struct DeviceInfo {
     pub host_info: HostInfo,
+    /// The main platform first, followed by the alternative ones
+    pub platforms: Vec<String>,
+}
+
+impl DeviceInfo {
+    pub fn new(host_info: HostInfo, platform_name: &str, alt_platform: &[AltPlatform]) -> Self {
+        let mut platforms = Vec::new();
+        if !platform_name.is_empty() {
+            platforms.push(platform_name.to_string());
+        }
+        for alt in alt_platform {
+            platforms.push(alt.name.clone());
+        }
+        DeviceInfo {
+            host_info,
+            platforms,
+        }
+    }
 }
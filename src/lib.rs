@@ -1,13 +1,29 @@
+mod config_error;
+pub use config_error::{ConfigError, ConfigProblem};
+
 mod app_state;
-pub use app_state::AppState;
+pub use app_state::{AppState, SharedAppState};
 
 pub mod boinc_api;
 
 pub mod planificator;
-pub use planificator::{planify_action, PlanificatorProject, PlanificatorResult};
+pub use planificator::{
+    planify_action, PlanificatorProject, PlanificatorResult, Planner, PlannerChain, PlannerConfig,
+};
 
 mod device_info;
 pub use device_info::DeviceInfo;
 
+mod result_state;
+pub use result_state::ResultState;
+
+pub mod boinc_crypt;
+
+mod tls;
+pub use tls::CertificateResolver;
+
 mod database;
-pub use database::{AppVersion, DataBase};
+pub use database::{
+    AppVersion, AppVersionFile, DataBase, Host, KeywordPreference, ProjectExclusion,
+    ProxyParseFailure, SchedulerExchange, Task, TaskIter, TaskQuery, User, WorkUnit,
+};
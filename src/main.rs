@@ -1,26 +1,312 @@
-use actix_web::{middleware::Logger, web::Data, App, HttpServer};
-use boinc_accoung_manager_rs::{boinc_api, AppState, DataBase};
-use clap::Parser;
+use actix_web::{
+    middleware::Logger,
+    rt::signal::unix::{signal, SignalKind},
+    web::Data,
+    App, HttpServer,
+};
+use anyhow::{bail, Context};
+use boinc_accoung_manager_rs::{
+    boinc_api, boinc_crypt, AppState, CertificateResolver, DataBase, KeywordPreference,
+    SharedAppState, User,
+};
+use clap::{Args as ClapArgs, Parser, Subcommand, ValueEnum};
+use log::{error, info, warn};
 use std::fs::File;
-use std::path::PathBuf;
+use std::io::{IsTerminal, Write};
+use std::net::SocketAddr;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 
 #[derive(Parser, Debug)]
 struct Args {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Run the account manager server. The config and TLS certificate are reloaded on SIGHUP.
+    Serve(ServeArgs),
+    /// Create a user account, or change the password of an existing one. The password is read
+    /// from the first line of the standard input, so it doesn’t show in the shell history.
+    AddUser { database: PathBuf, name: String },
+    /// Set how a user feel about a project keyword, like a science area. Projects with a keyword
+    /// set to no are never attached to the hosts of the user, those set to yes get more work.
+    SetPreference {
+        database: PathBuf,
+        name: String,
+        keyword: String,
+        #[arg(value_enum)]
+        preference: PreferenceArg,
+    },
+    /// Generate the key pair used to sign the project urls, in BOINC’s format. The public key is
+    /// the one to set as signing_key_path. Existing files are never overwritten.
+    Keygen {
+        private_key: PathBuf,
+        public_key: PathBuf,
+    },
+    /// Write the url signature of every project of the config in its signature_folder
+    SignUrls {
+        config: PathBuf,
+        private_key: PathBuf,
+    },
+    /// Report every problem in the config, including invalid url signatures, and exit with an
+    /// error code if there is any
+    CheckConfig { config: PathBuf },
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum PreferenceArg {
+    Yes,
+    No,
+    /// Remove the preference
+    Maybe,
+}
+
+#[derive(ClapArgs, Debug)]
+struct ServeArgs {
     config: PathBuf,
     database: PathBuf,
+    /// Address to listen on, like 127.0.0.1:8080 or [::]:443. Can be repeated. Override the
+    /// listen key of the config, default to 127.0.0.1:8080.
+    #[arg(long)]
+    listen: Vec<SocketAddr>,
+    /// PEM certificate chain, to serve HTTPS. Override the tls_cert key of the config.
+    #[arg(long, requires = "tls_key")]
+    tls_cert: Option<PathBuf>,
+    /// PEM private key of the certificate. Override the tls_key key of the config.
+    #[arg(long, requires = "tls_cert")]
+    tls_key: Option<PathBuf>,
+    /// Refuse to start, or to reload the config, if a project url signature is invalid
+    #[arg(long)]
+    strict: bool,
 }
 
 #[actix_web::main]
-async fn main() -> std::io::Result<()> {
+async fn main() -> anyhow::Result<()> {
     env_logger::init();
 
     let args = Args::parse();
 
-    let database = DataBase::new(&args.database).unwrap();
+    match args.command {
+        Command::Serve(serve_args) => serve(serve_args).await,
+        Command::AddUser { database, name } => {
+            let password = read_password()?;
+            let database = DataBase::new(&database)?;
+            database.add_user(&User::from_password(&name, &password))?;
+            Ok(())
+        }
+        Command::SetPreference {
+            database,
+            name,
+            keyword,
+            preference,
+        } => {
+            let database = DataBase::new(&database)?;
+            if database.get_user(&name)?.is_none() {
+                bail!("No user named “{}”", name);
+            }
+            let preference = match preference {
+                PreferenceArg::Yes => Some(KeywordPreference::Yes),
+                PreferenceArg::No => Some(KeywordPreference::No),
+                PreferenceArg::Maybe => None,
+            };
+            database.set_keyword_preference(&name, &keyword, preference)?;
+            Ok(())
+        }
+        Command::Keygen {
+            private_key,
+            public_key,
+        } => {
+            let key = boinc_crypt::generate_private_key()?;
+            // only readable by its owner
+            write_new_file(&private_key, &boinc_crypt::format_private_key(&key)?, 0o600)?;
+            write_new_file(
+                &public_key,
+                &boinc_crypt::format_public_key(&key.to_public_key())?,
+                0o644,
+            )?;
+            Ok(())
+        }
+        Command::SignUrls {
+            config,
+            private_key,
+        } => {
+            let private_key = boinc_crypt::parse_private_key(
+                &std::fs::read_to_string(&private_key)
+                    .with_context(|| format!("Reading {}", private_key.display()))?,
+            )?;
+            AppState::write_url_signatures(
+                &mut File::open(&config)
+                    .with_context(|| format!("Opening {}", config.display()))?,
+                &private_key,
+            )?;
+            Ok(())
+        }
+        Command::CheckConfig { config } => {
+            AppState::new(
+                &mut File::open(&config)
+                    .with_context(|| format!("Opening {}", config.display()))?,
+                DataBase::new_in_memory()?,
+                true,
+            )?;
+            println!("{} is valid", config.display());
+            Ok(())
+        }
+    }
+}
+
+fn read_password() -> anyhow::Result<String> {
+    let stdin = std::io::stdin();
+    if stdin.is_terminal() {
+        eprint!("Password: ");
+    }
+    let mut password = String::new();
+    stdin
+        .read_line(&mut password)
+        .context("Reading the password")?;
+    let password = password.trim_end_matches(['\r', '\n']);
+    if password.is_empty() {
+        bail!("The password can’t be empty");
+    }
+    Ok(password.to_string())
+}
+
+/// mode is the unix permissions of the file, before the umask
+fn write_new_file(path: &Path, content: &str, mode: u32) -> anyhow::Result<()> {
+    File::options()
+        .write(true)
+        .create_new(true)
+        .mode(mode)
+        .open(path)
+        .and_then(|mut file| file.write_all(content.as_bytes()))
+        .with_context(|| format!("Writing {}", path.display()))
+}
+
+/// The BOINC client reach the proxy through base_url, so it should use the same scheme as the
+/// listener, unless there is a reverse proxy doing TLS in front of us.
+fn check_base_url_scheme(base_url: &str, tls: bool) -> anyhow::Result<()> {
+    if base_url.starts_with("https://") {
+        if !tls {
+            warn!(
+                "base_url {} is https, but the server listen over plain http. This is only fine behind a TLS reverse proxy.",
+                base_url
+            );
+        }
+    } else if base_url.starts_with("http://") {
+        if tls {
+            bail!(
+                "base_url {} is http, but the server only listen over https",
+                base_url
+            );
+        }
+    } else {
+        bail!(
+            "base_url {} should start with http:// or https://",
+            base_url
+        );
+    }
+    Ok(())
+}
+
+/// Check a config reloaded on SIGHUP like at startup, and warn about the settings that are only
+/// read at startup. listen_from_config and tls_from_config are false when the command line
+/// overrides them.
+fn check_reloaded_config(
+    current: &AppState,
+    new: &AppState,
+    tls: bool,
+    listen_from_config: bool,
+    tls_from_config: bool,
+) -> anyhow::Result<()> {
+    check_base_url_scheme(&new.base_url, tls)?;
+    if listen_from_config && new.listen != current.listen {
+        warn!("listen changed in the config, it is only applied on restart");
+    }
+    if tls_from_config && (new.tls_cert != current.tls_cert || new.tls_key != current.tls_key) {
+        warn!("tls_cert or tls_key changed in the config, they are only applied on restart");
+    }
+    Ok(())
+}
+
+async fn serve(args: ServeArgs) -> anyhow::Result<()> {
+    let database = DataBase::new(&args.database)?;
+
+    let app_state = AppState::new(
+        &mut File::open(&args.config)
+            .with_context(|| format!("Opening {}", args.config.display()))?,
+        database,
+        args.strict,
+    )?;
+
+    let listen_from_config = args.listen.is_empty();
+    let tls_from_config = args.tls_cert.is_none();
+    let mut listen = if listen_from_config {
+        app_state.listen.clone()
+    } else {
+        args.listen
+    };
+    if listen.is_empty() {
+        listen.push(SocketAddr::from(([127, 0, 0, 1], 8080)));
+    }
+
+    let certificate_resolver = match (
+        args.tls_cert.or_else(|| app_state.tls_cert.clone()),
+        args.tls_key.or_else(|| app_state.tls_key.clone()),
+    ) {
+        (Some(cert), Some(key)) => Some(Arc::new(CertificateResolver::new(cert, key)?)),
+        (None, None) => None,
+        _ => bail!("tls_cert and tls_key should be set together"),
+    };
+
+    check_base_url_scheme(&app_state.base_url, certificate_resolver.is_some())?;
+
+    let state = SharedAppState::new(app_state);
 
-    let state = AppState::new(&mut File::open(&args.config).unwrap(), database).unwrap();
+    let mut hangup = signal(SignalKind::hangup())?;
+    let reloaded_state = state.clone();
+    let reloaded_certificate = certificate_resolver.clone();
+    let config = args.config;
+    let strict = args.strict;
+    let tls = certificate_resolver.is_some();
+    actix_web::rt::spawn(async move {
+        while hangup.recv().await.is_some() {
+            info!("SIGHUP received, reloading {}", config.display());
+            let reload_result =
+                File::open(&config)
+                    .map_err(anyhow::Error::from)
+                    .and_then(|mut file| {
+                        reloaded_state.reload(&mut file, strict, |current, new| {
+                            check_reloaded_config(
+                                current,
+                                new,
+                                tls,
+                                listen_from_config,
+                                tls_from_config,
+                            )
+                        })
+                    });
+            match reload_result {
+                Ok(()) => info!("config reloaded"),
+                Err(err) => error!(
+                    "failed to reload the config, keeping the old one: {:?}",
+                    err
+                ),
+            }
+            if let Some(resolver) = &reloaded_certificate {
+                match resolver.reload() {
+                    Ok(()) => info!("TLS certificate reloaded"),
+                    Err(err) => error!(
+                        "failed to reload the TLS certificate, keeping the old one: {:?}",
+                        err
+                    ),
+                }
+            }
+        }
+    });
 
-    HttpServer::new(move || {
+    let mut server = HttpServer::new(move || {
         App::new()
             .wrap(Logger::default())
             .app_data(Data::new(state.clone()))
@@ -28,8 +314,20 @@ async fn main() -> std::io::Result<()> {
             .service(boinc_api::rpc_endpoint)
             .service(boinc_api::proxy_root_route)
             .service(boinc_api::proxy_scheduler_route)
-    })
-    .bind(("127.0.0.1", 8080))?
-    .run()
-    .await
+    });
+    for address in listen {
+        server = match &certificate_resolver {
+            Some(resolver) => {
+                info!("listening on https://{}", address);
+                server.bind_rustls(address, resolver.clone().server_config())
+            }
+            None => {
+                info!("listening on http://{}", address);
+                server.bind(address)
+            }
+        }
+        .with_context(|| format!("Binding to {}", address))?;
+    }
+    server.run().await?;
+    Ok(())
 }
@@ -0,0 +1,20 @@
+use crate::{AppState, DeviceInfo, PlanificatorResult, Planner};
+
+/// Give the same priority to every project still in the plan
+pub struct BasePriorityPlanner {
+    pub priority: u16,
+}
+
+impl Planner for BasePriorityPlanner {
+    fn plan(
+        &self,
+        _app_state: &AppState,
+        _device_info: &DeviceInfo,
+        mut plan: PlanificatorResult,
+    ) -> PlanificatorResult {
+        for project in plan.projects.values_mut() {
+            project.priority = self.priority;
+        }
+        plan
+    }
+}
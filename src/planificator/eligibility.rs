@@ -0,0 +1,377 @@
+use regex::Regex;
+use serde::Deserialize;
+
+use crate::{AppState, DeviceInfo, PlanificatorResult, Planner};
+
+/// Conditions on a host, as written in config.json. Every condition set must match.
+#[derive(Deserialize, Debug, Default, Clone)]
+pub struct JsonHostMatcher {
+    /// regex searched in the OS name
+    os_name: Option<String>,
+    /// inclusive bounds, compared with the first dotted number found in the OS version
+    os_version_min: Option<String>,
+    os_version_max: Option<String>,
+    min_ram_bytes: Option<f64>,
+    min_cpus: Option<u32>,
+    /// one of the platforms supported by the host, like x86_64-pc-linux-gnu
+    platform: Option<String>,
+    /// nvidia, amd, intel, or the type of another coprocessor
+    gpu_vendor: Option<String>,
+}
+
+#[derive(Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum IneligibleAction {
+    /// Detach the project from the host, aborting its tasks
+    #[default]
+    Detach,
+    /// Keep the project attached with a resource share of 0, so it only get work when the others
+    /// have none
+    ZeroShare,
+}
+
+/// The eligibility key of a project in config.json
+#[derive(Deserialize, Debug, Default, Clone)]
+pub struct JsonEligibilityRules {
+    /// the host should match this
+    #[serde(default)]
+    require: JsonHostMatcher,
+    /// the host should match none of those
+    #[serde(default)]
+    exclude: Vec<JsonHostMatcher>,
+    #[serde(default)]
+    action: IneligibleAction,
+}
+
+#[derive(Debug, Clone)]
+pub struct HostMatcher {
+    os_name: Option<Regex>,
+    os_version_min: Option<Vec<u64>>,
+    os_version_max: Option<Vec<u64>>,
+    min_ram_bytes: Option<f64>,
+    min_cpus: Option<u32>,
+    platform: Option<String>,
+    gpu_vendor: Option<String>,
+}
+
+/// Extract the first dotted number, like 22.04 in “Ubuntu 22.04.3 LTS [5.15.0-91-generic]”
+fn parse_version(text: &str) -> Option<Vec<u64>> {
+    let start = text.find(|c: char| c.is_ascii_digit())?;
+    let version = &text[start..];
+    let end = version
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(version.len());
+    version[..end]
+        .split('.')
+        .filter(|part| !part.is_empty())
+        .map(|part| part.parse().ok())
+        .collect()
+}
+
+impl HostMatcher {
+    pub fn from_json(json: &JsonHostMatcher) -> Result<Self, String> {
+        let parse_bound = |bound: &Option<String>| match bound {
+            Some(bound) => parse_version(bound)
+                .map(Some)
+                .ok_or_else(|| format!("invalid version “{}”", bound)),
+            None => Ok(None),
+        };
+        Ok(HostMatcher {
+            os_name: match &json.os_name {
+                Some(os_name) => Some(Regex::new(os_name).map_err(|err| err.to_string())?),
+                None => None,
+            },
+            os_version_min: parse_bound(&json.os_version_min)?,
+            os_version_max: parse_bound(&json.os_version_max)?,
+            min_ram_bytes: json.min_ram_bytes,
+            min_cpus: json.min_cpus,
+            platform: json.platform.clone(),
+            gpu_vendor: json.gpu_vendor.as_ref().map(|vendor| vendor.to_lowercase()),
+        })
+    }
+
+    pub fn matches(&self, device_info: &DeviceInfo) -> bool {
+        let host_info = &device_info.host_info;
+        if let Some(os_name) = &self.os_name {
+            if !os_name.is_match(&host_info.os_name) {
+                return false;
+            }
+        }
+        if self.os_version_min.is_some() || self.os_version_max.is_some() {
+            let version = match parse_version(&host_info.os_version) {
+                Some(version) => version,
+                None => return false,
+            };
+            if let Some(min) = &self.os_version_min {
+                if &version < min {
+                    return false;
+                }
+            }
+            if let Some(max) = &self.os_version_max {
+                // 22.04.3 is within a maximum of 22.04
+                if version[..version.len().min(max.len())] > max[..] {
+                    return false;
+                }
+            }
+        }
+        if let Some(min_ram_bytes) = self.min_ram_bytes {
+            if host_info.m_nbytes < min_ram_bytes {
+                return false;
+            }
+        }
+        if let Some(min_cpus) = self.min_cpus {
+            if host_info.p_ncpus < min_cpus {
+                return false;
+            }
+        }
+        if let Some(platform) = &self.platform {
+            if !device_info.platforms.contains(platform) {
+                return false;
+            }
+        }
+        if let Some(gpu_vendor) = &self.gpu_vendor {
+            if !host_info.gpu_vendors().contains(gpu_vendor) {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct EligibilityRules {
+    require: HostMatcher,
+    exclude: Vec<HostMatcher>,
+    pub action: IneligibleAction,
+}
+
+impl EligibilityRules {
+    pub fn from_json(json: &JsonEligibilityRules) -> Result<Self, String> {
+        Ok(EligibilityRules {
+            require: HostMatcher::from_json(&json.require)?,
+            exclude: json
+                .exclude
+                .iter()
+                .map(HostMatcher::from_json)
+                .collect::<Result<_, _>>()?,
+            action: json.action,
+        })
+    }
+
+    /// Exclude the hosts whose OS name contains os_name_contains, ignoring case
+    pub fn exclude_os_name(os_name_contains: &str) -> Self {
+        let json = JsonEligibilityRules {
+            exclude: vec![JsonHostMatcher {
+                os_name: Some(format!("(?i){}", regex::escape(os_name_contains))),
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        Self::from_json(&json).expect("an escaped string is a valid regex")
+    }
+
+    pub fn is_eligible(&self, device_info: &DeviceInfo) -> bool {
+        self.require.matches(device_info)
+            && !self
+                .exclude
+                .iter()
+                .any(|matcher| matcher.matches(device_info))
+    }
+}
+
+fn apply_rules(
+    rules: &EligibilityRules,
+    project_id: &str,
+    device_info: &DeviceInfo,
+    plan: &mut PlanificatorResult,
+) {
+    if rules.is_eligible(device_info) {
+        return;
+    }
+    match rules.action {
+        IneligibleAction::Detach => {
+            plan.projects.remove(project_id);
+        }
+        IneligibleAction::ZeroShare => {
+            if let Some(planned) = plan.projects.get_mut(project_id) {
+                planned.priority = 0;
+            }
+        }
+    }
+}
+
+/// Apply the eligibility rules of each project
+pub struct EligibilityPlanner;
+
+impl Planner for EligibilityPlanner {
+    fn plan(
+        &self,
+        app_state: &AppState,
+        device_info: &DeviceInfo,
+        mut plan: PlanificatorResult,
+    ) -> PlanificatorResult {
+        for (project_id, project) in &app_state.projects {
+            apply_rules(&project.eligibility, project_id, device_info, &mut plan);
+        }
+        plan
+    }
+}
+
+/// Apply the same rules to some projects, on top of their own eligibility rules
+pub struct ProjectsEligibilityPlanner {
+    pub projects: Vec<String>,
+    pub rules: EligibilityRules,
+}
+
+impl Planner for ProjectsEligibilityPlanner {
+    fn plan(
+        &self,
+        _app_state: &AppState,
+        device_info: &DeviceInfo,
+        mut plan: PlanificatorResult,
+    ) -> PlanificatorResult {
+        for project_id in &self.projects {
+            apply_rules(&self.rules, project_id, device_info, &mut plan);
+        }
+        plan
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        database::tests::host_info,
+        device_info::AltPlatform,
+        planificator::tests::{app_state, plan},
+        PlannerChain, PlannerConfig,
+    };
+
+    fn device(os_name: &str, os_version: &str, extra: &str) -> DeviceInfo {
+        DeviceInfo::new(
+            host_info(os_name, os_version, extra),
+            "x86_64-pc-linux-gnu",
+            &[AltPlatform {
+                name: "i686-pc-linux-gnu".to_string(),
+            }],
+        )
+    }
+
+    fn linux() -> DeviceInfo {
+        device(
+            "Linux Ubuntu",
+            "Ubuntu 22.04.3 LTS [5.15.0-91-generic]",
+            "<p_ncpus>8</p_ncpus><m_nbytes>16000000000</m_nbytes><coprocs><coproc_cuda><count>1</count></coproc_cuda></coprocs>",
+        )
+    }
+
+    fn matcher(json: &str) -> HostMatcher {
+        HostMatcher::from_json(&serde_json::from_str(json).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn versions() {
+        assert_eq!(parse_version("6.1"), Some(vec![6, 1]));
+        assert_eq!(
+            parse_version("Ubuntu 22.04.3 LTS [5.15.0-91-generic]"),
+            Some(vec![22, 4, 3])
+        );
+        assert_eq!(parse_version("13.2-RELEASE"), Some(vec![13, 2]));
+        assert_eq!(
+            parse_version("10.0.19045.00 (64-bit)"),
+            Some(vec![10, 0, 19045, 0])
+        );
+        assert_eq!(parse_version("v2."), Some(vec![2]));
+        assert_eq!(parse_version("1..2"), Some(vec![1, 2]));
+        assert_eq!(parse_version("rolling"), None);
+        assert_eq!(parse_version(""), None);
+        assert_eq!(parse_version("99999999999999999999999"), None);
+    }
+
+    #[test]
+    fn empty_matcher_matches_everything() {
+        assert!(matcher("{}").matches(&linux()));
+    }
+
+    #[test]
+    fn os_name() {
+        assert!(matcher(r#"{"os_name": "Ubuntu"}"#).matches(&linux()));
+        assert!(!matcher(r#"{"os_name": "ubuntu"}"#).matches(&linux()));
+        assert!(matcher(r#"{"os_name": "(?i)ubuntu"}"#).matches(&linux()));
+        assert!(!matcher(r#"{"os_name": "^Microsoft"}"#).matches(&linux()));
+    }
+
+    #[test]
+    fn os_version() {
+        assert!(matcher(r#"{"os_version_min": "22.04"}"#).matches(&linux()));
+        assert!(!matcher(r#"{"os_version_min": "22.10"}"#).matches(&linux()));
+        assert!(matcher(r#"{"os_version_max": "22.04"}"#).matches(&linux()));
+        assert!(!matcher(r#"{"os_version_max": "20.04"}"#).matches(&linux()));
+        assert!(matcher(r#"{"os_version_min": "20", "os_version_max": "23"}"#).matches(&linux()));
+        // a host without a version never matches a version range
+        assert!(!matcher(r#"{"os_version_min": "1"}"#).matches(&device("Linux", "rolling", "")));
+    }
+
+    #[test]
+    fn ram_and_cpus() {
+        assert!(matcher(r#"{"min_ram_bytes": 8e9, "min_cpus": 8}"#).matches(&linux()));
+        assert!(!matcher(r#"{"min_ram_bytes": 32e9}"#).matches(&linux()));
+        assert!(!matcher(r#"{"min_cpus": 16}"#).matches(&linux()));
+    }
+
+    #[test]
+    fn platform() {
+        assert!(matcher(r#"{"platform": "x86_64-pc-linux-gnu"}"#).matches(&linux()));
+        assert!(matcher(r#"{"platform": "i686-pc-linux-gnu"}"#).matches(&linux()));
+        assert!(!matcher(r#"{"platform": "aarch64-unknown-linux-gnu"}"#).matches(&linux()));
+    }
+
+    #[test]
+    fn gpu_vendor() {
+        assert!(matcher(r#"{"gpu_vendor": "NVIDIA"}"#).matches(&linux()));
+        assert!(!matcher(r#"{"gpu_vendor": "amd"}"#).matches(&linux()));
+        assert!(!matcher(r#"{"gpu_vendor": "nvidia"}"#).matches(&device("Linux", "6.1", "")));
+    }
+
+    #[test]
+    fn invalid_rules() {
+        let invalid =
+            |json: &str| HostMatcher::from_json(&serde_json::from_str(json).unwrap()).is_err();
+        assert!(invalid(r#"{"os_name": "("}"#));
+        assert!(invalid(r#"{"os_version_min": "latest"}"#));
+    }
+
+    #[test]
+    fn require_and_exclude() {
+        let rules =
+            |json: &str| EligibilityRules::from_json(&serde_json::from_str(json).unwrap()).unwrap();
+        assert!(rules(r#"{"require": {"min_cpus": 4}}"#).is_eligible(&linux()));
+        assert!(!rules(r#"{"require": {"min_cpus": 4}, "exclude": [{"gpu_vendor": "intel"}, {"os_name": "Ubuntu"}]}"#).is_eligible(&linux()));
+        assert!(!EligibilityRules::exclude_os_name("ubuntu").is_eligible(&linux()));
+        assert!(EligibilityRules::exclude_os_name("Ubuntu.").is_eligible(&linux()));
+    }
+
+    #[test]
+    fn exclude_on_os_config() {
+        let config: Vec<PlannerConfig> = serde_json::from_str(
+            r#"[{"type": "exclude_on_os", "os_name_contains": "NixOS", "projects": ["loda"]}]"#,
+        )
+        .unwrap();
+        let chain = PlannerChain::from_config(&config);
+        let nixos = device("Linux NixOS", "23.11", "");
+        let planned = chain.plan(&app_state(), &nixos, plan(&["loda", "other"], 100));
+        assert!(!planned.projects.contains_key("loda"));
+        assert!(planned.projects.contains_key("other"));
+        let planned = chain.plan(&app_state(), &linux(), plan(&["loda"], 100));
+        assert!(planned.projects.contains_key("loda"));
+    }
+
+    #[test]
+    fn default_chain_keeps_loda_off_nixos() {
+        let has_rule = PlannerConfig::default_chain().iter().any(|planner| {
+            matches!(planner, PlannerConfig::ExcludeOnOs { os_name_contains, projects }
+                if os_name_contains == "nixos" && projects == &["loda"])
+        });
+        assert!(has_rule);
+    }
+}
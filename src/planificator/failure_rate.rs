@@ -0,0 +1,301 @@
+use log::{info, warn};
+use serde::Deserialize;
+
+use crate::{
+    database::unix_timestamp, AppState, DeviceInfo, PlanificatorResult, Planner, ProjectExclusion,
+};
+
+/// What to do with a project that fail too often on a host
+#[derive(Deserialize, Debug, Default, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum FailureAction {
+    #[default]
+    Detach,
+    /// Multiply the priority by this factor
+    ReduceShare(f64),
+}
+
+impl FailureAction {
+    fn share_factor(&self) -> Option<f64> {
+        match self {
+            Self::Detach => None,
+            Self::ReduceShare(factor) => Some(*factor),
+        }
+    }
+}
+
+/// Exclude, for a cool-down period, the projects whose results often fail on this host
+pub struct FailureRatePlanner {
+    /// only count the results sent in this many seconds
+    pub window: u64,
+    /// ratio of failed over finished results above which the project is excluded
+    pub threshold: f64,
+    /// don’t judge a project on fewer finished results than this
+    pub min_results: u64,
+    /// how long the exclusion last, in seconds
+    pub cool_down: u64,
+    pub action: FailureAction,
+}
+
+fn apply_exclusion(plan: &mut PlanificatorResult, exclusion: &ProjectExclusion) {
+    match exclusion.share_factor {
+        None => {
+            plan.projects.remove(&exclusion.project);
+        }
+        Some(factor) => {
+            if let Some(project) = plan.projects.get_mut(&exclusion.project) {
+                project.priority = (project.priority as f64 * factor) as u16;
+            }
+        }
+    }
+}
+
+impl FailureRatePlanner {
+    /// Return the exclusion in effect for the project, recording a new one if needed
+    fn check_project(
+        &self,
+        app_state: &AppState,
+        cpid: &str,
+        host_id: i64,
+        project_id: &str,
+        now: u64,
+    ) -> anyhow::Result<Option<ProjectExclusion>> {
+        let database = &app_state.database;
+        let last_exclusion = database.get_last_project_exclusion(host_id, project_id)?;
+        let mut since = now.saturating_sub(self.window);
+        if let Some(last_exclusion) = last_exclusion {
+            if last_exclusion.until > now {
+                return Ok(Some(last_exclusion));
+            }
+            // the failures that caused the previous exclusion were already paid for
+            since = since.max(last_exclusion.until);
+        }
+
+        let (failed, finished) = database.count_result_outcomes(cpid, project_id, since)?;
+        if finished < self.min_results || (failed as f64) <= self.threshold * finished as f64 {
+            return Ok(None);
+        }
+        let exclusion = ProjectExclusion {
+            host_id,
+            project: project_id.to_string(),
+            started: now,
+            until: now + self.cool_down,
+            failed_results: failed,
+            finished_results: finished,
+            share_factor: self.action.share_factor(),
+        };
+        info!(
+            "{} failed {} of its {} results on host {}, applying {:?} until {}",
+            project_id, failed, finished, host_id, self.action, exclusion.until
+        );
+        database.add_project_exclusion(&exclusion)?;
+        Ok(Some(exclusion))
+    }
+}
+
+impl Planner for FailureRatePlanner {
+    fn plan(
+        &self,
+        app_state: &AppState,
+        device_info: &DeviceInfo,
+        mut plan: PlanificatorResult,
+    ) -> PlanificatorResult {
+        let cpid = &device_info.host_info.host_cpid;
+        let host_id = match app_state.database.get_host_id(cpid) {
+            Ok(Some(host_id)) => host_id,
+            // a host we never saw has no history
+            Ok(None) => return plan,
+            Err(err) => {
+                warn!("can’t look up the host {}: {:#}", cpid, err);
+                return plan;
+            }
+        };
+        let now = unix_timestamp();
+        let project_ids: Vec<String> = plan.projects.keys().cloned().collect();
+        for project_id in project_ids {
+            match self.check_project(app_state, cpid, host_id, &project_id, now) {
+                Ok(Some(exclusion)) => apply_exclusion(&mut plan, &exclusion),
+                Ok(None) => {}
+                Err(err) => warn!(
+                    "can’t check the failure rate of {} on host {}: {:#}",
+                    project_id, host_id, err
+                ),
+            }
+        }
+        plan
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        database::tests::{host_info, task},
+        planificator::tests::{app_state, plan},
+        PlannerConfig, ResultState, Task,
+    };
+
+    const NOW: u64 = 1_000_000;
+
+    fn planner(action: FailureAction) -> FailureRatePlanner {
+        FailureRatePlanner {
+            window: 1000,
+            threshold: 0.5,
+            min_results: 4,
+            cool_down: 500,
+            action,
+        }
+    }
+
+    /// An AppState whose host “cpid” is known, and its id
+    fn host() -> (AppState, i64) {
+        let app_state = app_state();
+        let host_id = app_state
+            .database
+            .upsert_host(
+                &DeviceInfo::new(host_info("Linux", "6.1", ""), "", &[]),
+                NOW,
+            )
+            .unwrap();
+        (app_state, host_id)
+    }
+
+    /// Record results of project “a” sent at timestamp
+    fn results(app_state: &AppState, timestamp: u64, failed: usize, uploaded: usize) {
+        let statuses = std::iter::repeat_n(ResultState::ComputeError, failed)
+            .chain(std::iter::repeat_n(ResultState::FilesUploaded, uploaded));
+        for (index, status) in statuses.enumerate() {
+            app_state
+                .database
+                .add_task(&Task {
+                    status,
+                    ..task("a", &format!("r_{}_{}", timestamp, index), timestamp)
+                })
+                .unwrap();
+        }
+    }
+
+    #[test]
+    fn too_few_results() {
+        let (app_state, host_id) = host();
+        results(&app_state, NOW - 10, 3, 0);
+        let planner = planner(FailureAction::Detach);
+        assert!(planner
+            .check_project(&app_state, "cpid", host_id, "a", NOW)
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn threshold() {
+        let (app_state, host_id) = host();
+        let planner = planner(FailureAction::Detach);
+        // exactly at the threshold
+        results(&app_state, NOW - 10, 2, 2);
+        assert!(planner
+            .check_project(&app_state, "cpid", host_id, "a", NOW)
+            .unwrap()
+            .is_none());
+        // results sent before the window don’t count
+        results(&app_state, NOW - 2000, 10, 0);
+        assert!(planner
+            .check_project(&app_state, "cpid", host_id, "a", NOW)
+            .unwrap()
+            .is_none());
+
+        results(&app_state, NOW - 5, 1, 0);
+        let exclusion = planner
+            .check_project(&app_state, "cpid", host_id, "a", NOW)
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            (exclusion.failed_results, exclusion.finished_results),
+            (3, 5)
+        );
+        assert_eq!(exclusion.until, NOW + 500);
+        assert_eq!(exclusion.share_factor, None);
+    }
+
+    #[test]
+    fn cool_down() {
+        let (app_state, host_id) = host();
+        let planner = planner(FailureAction::Detach);
+        results(&app_state, NOW - 10, 4, 0);
+        let exclusion = planner
+            .check_project(&app_state, "cpid", host_id, "a", NOW)
+            .unwrap()
+            .unwrap();
+
+        // still excluded, without recording a new exclusion
+        let during = planner
+            .check_project(&app_state, "cpid", host_id, "a", NOW + 100)
+            .unwrap()
+            .unwrap();
+        assert_eq!(during.started, exclusion.started);
+
+        // once the cool-down is over, the failures that caused it no longer count
+        assert!(planner
+            .check_project(&app_state, "cpid", host_id, "a", exclusion.until + 1)
+            .unwrap()
+            .is_none());
+
+        results(&app_state, exclusion.until + 10, 4, 0);
+        let again = planner
+            .check_project(&app_state, "cpid", host_id, "a", exclusion.until + 20)
+            .unwrap()
+            .unwrap();
+        assert_eq!(again.started, exclusion.until + 20);
+        assert_eq!(again.finished_results, 4);
+    }
+
+    #[test]
+    fn actions() {
+        let exclusion = |share_factor| ProjectExclusion {
+            host_id: 1,
+            project: "a".to_string(),
+            started: NOW,
+            until: NOW + 500,
+            failed_results: 4,
+            finished_results: 4,
+            share_factor,
+        };
+        let mut detached = plan(&["a", "b"], 100);
+        apply_exclusion(
+            &mut detached,
+            &exclusion(FailureAction::Detach.share_factor()),
+        );
+        assert!(!detached.projects.contains_key("a"));
+        assert_eq!(detached.projects["b"].priority, 100);
+
+        let mut reduced = plan(&["a", "b"], 100);
+        apply_exclusion(
+            &mut reduced,
+            &exclusion(FailureAction::ReduceShare(0.25).share_factor()),
+        );
+        assert_eq!(reduced.projects["a"].priority, 25);
+        assert_eq!(reduced.projects["b"].priority, 100);
+
+        let mut zeroed = plan(&["a"], 100);
+        apply_exclusion(
+            &mut zeroed,
+            &exclusion(FailureAction::ReduceShare(0.0).share_factor()),
+        );
+        assert_eq!(zeroed.projects["a"].priority, 0);
+    }
+
+    #[test]
+    fn factor_validation() {
+        let config = |action: &str| {
+            let config: PlannerConfig = serde_json::from_str(&format!(
+                r#"{{"type": "failure_rate", "action": {}}}"#,
+                action
+            ))
+            .unwrap();
+            config.validate()
+        };
+        assert!(config(r#""detach""#).is_ok());
+        assert!(config(r#"{"reduce_share": 0.5}"#).is_ok());
+        assert!(config(r#"{"reduce_share": 1.5}"#).is_err());
+        assert!(config(r#"{"reduce_share": -1}"#).is_err());
+    }
+}
@@ -0,0 +1,298 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use serde::Deserialize;
+
+use crate::{AppState, DeviceInfo};
+
+mod base_priority;
+pub use base_priority::BasePriorityPlanner;
+
+mod eligibility;
+pub use eligibility::{
+    EligibilityPlanner, EligibilityRules, HostMatcher, IneligibleAction, JsonEligibilityRules,
+    ProjectsEligibilityPlanner,
+};
+
+mod failure_rate;
+pub use failure_rate::{FailureAction, FailureRatePlanner};
+
+mod resource_share;
+pub use resource_share::{compute_shares, desired_allocation, ResourceSharePlanner};
+
+pub struct PlanificatorProject {
+    pub priority: u16,
+}
+
+/// The projects to attach to the host, with their resource share. Projects missing from it are
+/// detached.
+pub struct PlanificatorResult {
+    /// A priority of 0 keep the project attached, but only as a backup project
+    pub projects: HashMap<String, PlanificatorProject>,
+}
+
+impl PlanificatorResult {
+    pub fn new_from_app_state(app_state: &AppState, default_priority: u16) -> Self {
+        let mut projects = HashMap::new();
+        for project_id in app_state.projects.keys() {
+            projects.insert(
+                project_id.to_string(),
+                PlanificatorProject {
+                    priority: default_priority,
+                },
+            );
+        }
+        PlanificatorResult { projects }
+    }
+}
+
+/// A planning strategy
+pub trait Planner: Send + Sync {
+    /// Refine the plan made by the previous planners of the chain. The first one receive every
+    /// project of the config with a priority of 0.
+    fn plan(
+        &self,
+        app_state: &AppState,
+        device_info: &DeviceInfo,
+        plan: PlanificatorResult,
+    ) -> PlanificatorResult;
+}
+
+/// Run planners one after the other
+#[derive(Clone)]
+pub struct PlannerChain {
+    planners: Vec<Arc<dyn Planner>>,
+}
+
+impl PlannerChain {
+    pub fn new(planners: Vec<Arc<dyn Planner>>) -> Self {
+        PlannerChain { planners }
+    }
+
+    pub fn from_config(config: &[PlannerConfig]) -> Self {
+        Self::new(config.iter().map(PlannerConfig::build).collect())
+    }
+}
+
+impl Planner for PlannerChain {
+    fn plan(
+        &self,
+        app_state: &AppState,
+        device_info: &DeviceInfo,
+        mut plan: PlanificatorResult,
+    ) -> PlanificatorResult {
+        for planner in &self.planners {
+            plan = planner.plan(app_state, device_info, plan);
+        }
+        plan
+    }
+}
+
+/// A planner, as listed in the planners key of config.json
+#[derive(Deserialize, Debug, Clone)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum PlannerConfig {
+    BasePriority {
+        priority: u16,
+    },
+    /// Exclude the projects whose results often fail on the host, for a cool-down period
+    FailureRate {
+        /// in seconds
+        #[serde(default = "default_failure_window")]
+        window: u64,
+        /// ratio of failed over finished results above which the project is excluded
+        #[serde(default = "default_failure_threshold")]
+        threshold: f64,
+        #[serde(default = "default_failure_min_results")]
+        min_results: u64,
+        /// in seconds
+        #[serde(default = "default_failure_cool_down")]
+        cool_down: u64,
+        /// "detach", or {"reduce_share": factor}
+        #[serde(default)]
+        action: FailureAction,
+    },
+    /// Apply the eligibility rules of each project. Should come after the planners that change
+    /// the priorities.
+    Eligibility,
+    /// Detach the projects from the hosts whose OS name contains os_name_contains, ignoring case.
+    /// Like an eligibility rule excluding this OS name, for the configs written before them.
+    ExcludeOnOs {
+        os_name_contains: String,
+        projects: Vec<String>,
+    },
+    /// Split the host between the projects from the keyword preferences of its user, correcting
+    /// for the FLOPs recently delivered. Replace the priorities set by the previous planners.
+    ResourceShare {
+        /// in seconds
+        #[serde(default = "default_share_window")]
+        window: u64,
+        /// in seconds, 0 to disable the decay
+        #[serde(default = "default_share_half_life")]
+        half_life: u64,
+        /// multiply the FLOPs sent for those projects, for those whose estimations are off
+        #[serde(default)]
+        flops_factor: HashMap<String, f64>,
+        #[serde(default = "default_total_share")]
+        total_share: u16,
+    },
+}
+
+fn default_share_window() -> u64 {
+    30 * 24 * 3600
+}
+
+fn default_share_half_life() -> u64 {
+    7 * 24 * 3600
+}
+
+fn default_total_share() -> u16 {
+    1000
+}
+
+fn default_failure_window() -> u64 {
+    7 * 24 * 3600
+}
+
+fn default_failure_threshold() -> f64 {
+    0.5
+}
+
+fn default_failure_min_results() -> u64 {
+    5
+}
+
+fn default_failure_cool_down() -> u64 {
+    3 * 24 * 3600
+}
+
+impl PlannerConfig {
+    pub fn build(&self) -> Arc<dyn Planner> {
+        match self {
+            Self::BasePriority { priority } => Arc::new(BasePriorityPlanner {
+                priority: *priority,
+            }),
+            Self::FailureRate {
+                window,
+                threshold,
+                min_results,
+                cool_down,
+                action,
+            } => Arc::new(FailureRatePlanner {
+                window: *window,
+                threshold: *threshold,
+                min_results: *min_results,
+                cool_down: *cool_down,
+                action: *action,
+            }),
+            Self::Eligibility => Arc::new(EligibilityPlanner),
+            Self::ExcludeOnOs {
+                os_name_contains,
+                projects,
+            } => Arc::new(ProjectsEligibilityPlanner {
+                projects: projects.clone(),
+                rules: EligibilityRules::exclude_os_name(os_name_contains),
+            }),
+            Self::ResourceShare {
+                window,
+                half_life,
+                flops_factor,
+                total_share,
+            } => Arc::new(ResourceSharePlanner {
+                window: *window,
+                half_life: *half_life,
+                flops_factor: flops_factor.clone(),
+                total_share: *total_share,
+            }),
+        }
+    }
+
+    /// Check the values that would make the planner misbehave
+    pub fn validate(&self) -> Result<(), String> {
+        match self {
+            Self::FailureRate {
+                threshold, action, ..
+            } => {
+                if !(threshold.is_finite() && *threshold >= 0.0) {
+                    return Err(format!(
+                        "threshold {} should be a positive ratio",
+                        threshold
+                    ));
+                }
+                if let FailureAction::ReduceShare(factor) = action {
+                    if !(0.0..=1.0).contains(factor) {
+                        return Err(format!(
+                            "reduce_share factor {} should be between 0 and 1",
+                            factor
+                        ));
+                    }
+                }
+                Ok(())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// The chain used when config.json doesn’t list any planner
+    pub fn default_chain() -> Vec<Self> {
+        vec![
+            Self::ResourceShare {
+                window: default_share_window(),
+                half_life: default_share_half_life(),
+                flops_factor: HashMap::from([("worldcommunitygrid".to_string(), 0.5)]),
+                total_share: default_total_share(),
+            },
+            Self::FailureRate {
+                window: default_failure_window(),
+                threshold: default_failure_threshold(),
+                min_results: default_failure_min_results(),
+                cool_down: default_failure_cool_down(),
+                action: FailureAction::Detach,
+            },
+            Self::Eligibility,
+            Self::ExcludeOnOs {
+                os_name_contains: "nixos".to_string(),
+                projects: vec!["loda".to_string()],
+            },
+        ]
+    }
+}
+
+pub fn planify_action(app_state: &AppState, device_info: &DeviceInfo) -> PlanificatorResult {
+    app_state.planner.plan(
+        app_state,
+        device_info,
+        PlanificatorResult::new_from_app_state(app_state, 0),
+    )
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::DataBase;
+
+    /// An AppState without projects, for testing the planners that don’t read them
+    pub fn app_state() -> AppState {
+        AppState {
+            projects: HashMap::new(),
+            account_manager_name: String::new(),
+            signing_key: String::new(),
+            base_url: String::new(),
+            database: DataBase::new_in_memory().unwrap(),
+            strict_reply_parsing: false,
+            listen: Vec::new(),
+            tls_cert: None,
+            tls_key: None,
+            planner: PlannerChain::new(Vec::new()),
+        }
+    }
+
+    pub fn plan(projects: &[&str], priority: u16) -> PlanificatorResult {
+        PlanificatorResult {
+            projects: projects
+                .iter()
+                .map(|project_id| (project_id.to_string(), PlanificatorProject { priority }))
+                .collect(),
+        }
+    }
+}
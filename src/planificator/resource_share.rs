@@ -0,0 +1,287 @@
+//! Resource shares in the spirit of Science United: the user preferences give each project a
+//! desired fraction of the host’s computing power, and the shares are then adjusted so the FLOPs
+//! actually delivered converge to that allocation.
+
+use std::collections::HashMap;
+
+use log::{debug, warn};
+
+use crate::{
+    database::unix_timestamp, AppState, DeviceInfo, KeywordPreference, PlanificatorResult, Planner,
+};
+
+/// Bound of the correction applied to the desired fraction, so a project that received nothing
+/// yet doesn’t take the whole host
+const MAX_GAIN: f64 = 4.0;
+
+/// The desired fraction of the host for each project, from its keywords. Projects with a keyword
+/// the user said no to are left out. Each keyword the user said yes to add the base weight again.
+pub fn desired_allocation(
+    project_keywords: &HashMap<String, Vec<String>>,
+    preferences: &HashMap<String, KeywordPreference>,
+) -> HashMap<String, f64> {
+    let mut weights = HashMap::new();
+    for (project_id, keywords) in project_keywords {
+        let mut weight = 1.0;
+        let mut excluded = false;
+        for keyword in keywords {
+            match preferences.get(keyword) {
+                Some(KeywordPreference::Yes) => weight += 1.0,
+                Some(KeywordPreference::No) => excluded = true,
+                None => {}
+            }
+        }
+        if !excluded {
+            weights.insert(project_id.clone(), weight);
+        }
+    }
+    normalize(weights)
+}
+
+/// The fraction of the resource share to give each project, so the delivered FLOPs move toward
+/// the desired allocation. A project behind its target get up to MAX_GAIN times its desired
+/// fraction, one ahead down to 1/MAX_GAIN. FLOPs delivered for projects missing from desired are
+/// ignored.
+pub fn compute_shares(
+    desired: &HashMap<String, f64>,
+    delivered: &HashMap<String, f64>,
+) -> HashMap<String, f64> {
+    let delivered_of = |project_id: &String| delivered.get(project_id).copied().unwrap_or(0.0);
+    let total_delivered: f64 = desired.keys().map(delivered_of).sum();
+    let mut shares = HashMap::new();
+    for (project_id, desired_fraction) in desired {
+        let gain = if total_delivered <= 0.0 {
+            1.0
+        } else {
+            let actual_fraction = delivered_of(project_id) / total_delivered;
+            if actual_fraction <= 0.0 {
+                MAX_GAIN
+            } else {
+                (desired_fraction / actual_fraction).clamp(1.0 / MAX_GAIN, MAX_GAIN)
+            }
+        };
+        shares.insert(project_id.clone(), desired_fraction * gain);
+    }
+    normalize(shares)
+}
+
+fn normalize(mut values: HashMap<String, f64>) -> HashMap<String, f64> {
+    let total: f64 = values.values().sum();
+    if total > 0.0 {
+        for value in values.values_mut() {
+            *value /= total;
+        }
+    }
+    values
+}
+
+/// Set the priorities from the keyword preferences of the host’s user and the FLOPs recently
+/// delivered by the host
+pub struct ResourceSharePlanner {
+    /// only count the work sent in this many seconds
+    pub window: u64,
+    /// the work sent this many seconds ago count half, 0 to count it all the same
+    pub half_life: u64,
+    /// multiply the FLOPs sent for those projects, for those whose estimations are off
+    pub flops_factor: HashMap<String, f64>,
+    /// sum of the priorities given to the projects
+    pub total_share: u16,
+}
+
+impl Planner for ResourceSharePlanner {
+    fn plan(
+        &self,
+        app_state: &AppState,
+        device_info: &DeviceInfo,
+        mut plan: PlanificatorResult,
+    ) -> PlanificatorResult {
+        let cpid = &device_info.host_info.host_cpid;
+        let preferences = app_state
+            .database
+            .get_host_keyword_preferences(cpid)
+            .unwrap_or_else(|err| {
+                warn!("can’t read the preferences for host {}: {:#}", cpid, err);
+                HashMap::new()
+            });
+        let project_keywords = plan
+            .projects
+            .keys()
+            .filter_map(|project_id| {
+                let project = app_state.projects.get(project_id)?;
+                Some((project_id.clone(), project.keywords.clone()))
+            })
+            .collect();
+        let desired = desired_allocation(&project_keywords, &preferences);
+
+        let now = unix_timestamp();
+        let decay_rate = if self.half_life == 0 {
+            0.0
+        } else {
+            std::f64::consts::LN_2 / self.half_life as f64
+        };
+        let mut delivered = app_state
+            .database
+            .sum_delivered_fpops(cpid, now.saturating_sub(self.window), now, decay_rate)
+            .unwrap_or_else(|err| {
+                warn!("can’t sum the FLOPs delivered by host {}: {:#}", cpid, err);
+                HashMap::new()
+            });
+        for (project_id, factor) in &self.flops_factor {
+            if let Some(flops) = delivered.get_mut(project_id) {
+                *flops *= factor;
+            }
+        }
+
+        let shares = compute_shares(&desired, &delivered);
+        plan.projects
+            .retain(|project_id, _| shares.contains_key(project_id));
+        for (project_id, project) in plan.projects.iter_mut() {
+            let share = shares[project_id];
+            debug!(
+                "{}: desired {}, share {}",
+                project_id, desired[project_id], share
+            );
+            // a priority of 0 would make it a backup project
+            project.priority = ((share * self.total_share as f64).round() as u16).max(1);
+        }
+        plan
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keywords(projects: &[(&str, &[&str])]) -> HashMap<String, Vec<String>> {
+        projects
+            .iter()
+            .map(|(project_id, keywords)| {
+                (
+                    project_id.to_string(),
+                    keywords.iter().map(|keyword| keyword.to_string()).collect(),
+                )
+            })
+            .collect()
+    }
+
+    fn fractions(values: &[(&str, f64)]) -> HashMap<String, f64> {
+        values
+            .iter()
+            .map(|(project_id, value)| (project_id.to_string(), *value))
+            .collect()
+    }
+
+    fn assert_close(actual: f64, expected: f64) {
+        assert!(
+            (actual - expected).abs() < 1e-9,
+            "{} != {}",
+            actual,
+            expected
+        );
+    }
+
+    #[test]
+    fn equal_allocation_without_preferences() {
+        let desired = desired_allocation(
+            &keywords(&[("a", &["biology"]), ("b", &["math"]), ("c", &[])]),
+            &HashMap::new(),
+        );
+        assert_eq!(desired.len(), 3);
+        for fraction in desired.values() {
+            assert_close(*fraction, 1.0 / 3.0);
+        }
+    }
+
+    #[test]
+    fn preferences_weight_and_exclude() {
+        let preferences = HashMap::from([
+            ("biology".to_string(), KeywordPreference::Yes),
+            ("medicine".to_string(), KeywordPreference::Yes),
+            ("cryptography".to_string(), KeywordPreference::No),
+        ]);
+        let desired = desired_allocation(
+            &keywords(&[
+                ("a", &["biology", "medicine"]),
+                ("b", &["math"]),
+                ("c", &["math", "cryptography"]),
+            ]),
+            &preferences,
+        );
+        assert!(!desired.contains_key("c"));
+        assert_close(desired["a"], 0.75);
+        assert_close(desired["b"], 0.25);
+    }
+
+    #[test]
+    fn everything_excluded() {
+        let preferences = HashMap::from([("math".to_string(), KeywordPreference::No)]);
+        let desired = desired_allocation(&keywords(&[("a", &["math"])]), &preferences);
+        assert!(desired.is_empty());
+        assert!(compute_shares(&desired, &HashMap::new()).is_empty());
+    }
+
+    #[test]
+    fn no_history_gives_the_desired_allocation() {
+        let desired = fractions(&[("a", 0.75), ("b", 0.25)]);
+        let shares = compute_shares(&desired, &HashMap::new());
+        assert_close(shares["a"], 0.75);
+        assert_close(shares["b"], 0.25);
+    }
+
+    #[test]
+    fn balanced_history_keeps_the_desired_allocation() {
+        let desired = fractions(&[("a", 0.75), ("b", 0.25)]);
+        let delivered = fractions(&[("a", 3e15), ("b", 1e15)]);
+        let shares = compute_shares(&desired, &delivered);
+        assert_close(shares["a"], 0.75);
+        assert_close(shares["b"], 0.25);
+    }
+
+    #[test]
+    fn behind_projects_catch_up() {
+        let desired = fractions(&[("a", 0.5), ("b", 0.5)]);
+        // b only got a quarter, and the FLOPs of an excluded project don’t count
+        let delivered = fractions(&[("a", 3e15), ("b", 1e15), ("excluded", 1e18)]);
+        let shares = compute_shares(&desired, &delivered);
+        // a gets 0.5 * 2/3, b gets 0.5 * 2
+        assert_close(shares["a"], 0.25);
+        assert_close(shares["b"], 0.75);
+    }
+
+    #[test]
+    fn gain_is_bounded() {
+        let desired = fractions(&[("a", 0.1), ("b", 0.9)]);
+        let delivered = fractions(&[("a", 1e15)]);
+        let shares = compute_shares(&desired, &delivered);
+        // a is 10 times ahead and b got nothing, both are clamped
+        let a = 0.1 / MAX_GAIN;
+        let b = 0.9 * MAX_GAIN;
+        assert_close(shares["a"], a / (a + b));
+        assert_close(shares["b"], b / (a + b));
+    }
+
+    /// Simulate a host whose work split follow the shares, starting from a history where a
+    /// single project got everything
+    #[test]
+    fn converges_to_the_desired_allocation() {
+        let desired = fractions(&[("a", 0.5), ("b", 0.3), ("c", 0.2)]);
+        let mut delivered = fractions(&[("a", 1e16)]);
+        for _ in 0..200 {
+            let shares = compute_shares(&desired, &delivered);
+            for (project_id, share) in shares {
+                *delivered.entry(project_id).or_insert(0.0) += share * 1e15;
+            }
+        }
+        let total: f64 = delivered.values().sum();
+        for (project_id, fraction) in &desired {
+            let actual = delivered[project_id] / total;
+            assert!(
+                (actual - fraction).abs() < 0.01,
+                "{}: {} instead of {}",
+                project_id,
+                actual,
+                fraction
+            );
+        }
+    }
+}
@@ -0,0 +1,155 @@
+use rusqlite::types::{FromSql, FromSqlResult, ToSql, ToSqlOutput, ValueRef};
+use serde::{Deserialize, Serialize};
+
+/// The state of a result on the host, BOINC’s `RESULT_*` values. Unknown keeps any other value
+/// as-is, so it can be stored and sent back unchanged.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(from = "u32", into = "u32")]
+pub enum ResultState {
+    New,
+    FilesDownloading,
+    FilesDownloaded,
+    ComputeError,
+    FilesUploading,
+    FilesUploaded,
+    Aborted,
+    UploadFailed,
+    Unknown(u32),
+}
+
+impl ResultState {
+    /// The states a result can’t leave
+    pub const FINISHED: [Self; 4] = [
+        Self::ComputeError,
+        Self::FilesUploaded,
+        Self::Aborted,
+        Self::UploadFailed,
+    ];
+    /// The states of a task the host is still working on
+    pub const IN_PROGRESS: [Self; 4] = [
+        Self::New,
+        Self::FilesDownloading,
+        Self::FilesDownloaded,
+        Self::FilesUploading,
+    ];
+    /// The finished states where the work is lost
+    pub const FAILED: [Self; 3] = [Self::ComputeError, Self::Aborted, Self::UploadFailed];
+
+    /// The states as a comma-separated list of their values, for an SQL `IN (...)`
+    pub fn sql_list(states: &[Self]) -> String {
+        states
+            .iter()
+            .map(|state| u32::from(*state).to_string())
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+}
+
+impl From<u32> for ResultState {
+    fn from(value: u32) -> Self {
+        match value {
+            0 => Self::New,
+            1 => Self::FilesDownloading,
+            2 => Self::FilesDownloaded,
+            3 => Self::ComputeError,
+            4 => Self::FilesUploading,
+            5 => Self::FilesUploaded,
+            6 => Self::Aborted,
+            7 => Self::UploadFailed,
+            value => Self::Unknown(value),
+        }
+    }
+}
+
+impl From<ResultState> for u32 {
+    fn from(state: ResultState) -> Self {
+        match state {
+            ResultState::New => 0,
+            ResultState::FilesDownloading => 1,
+            ResultState::FilesDownloaded => 2,
+            ResultState::ComputeError => 3,
+            ResultState::FilesUploading => 4,
+            ResultState::FilesUploaded => 5,
+            ResultState::Aborted => 6,
+            ResultState::UploadFailed => 7,
+            ResultState::Unknown(value) => value,
+        }
+    }
+}
+
+impl ToSql for ResultState {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        Ok(ToSqlOutput::from(u32::from(*self)))
+    }
+}
+
+impl FromSql for ResultState {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        u32::column_result(value).map(Self::from)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rusqlite::Connection;
+
+    #[test]
+    fn integer_round_trip() {
+        for value in [0, 1, 2, 3, 4, 5, 6, 7, 8, 42, u32::MAX] {
+            assert_eq!(u32::from(ResultState::from(value)), value);
+        }
+        assert_eq!(ResultState::from(6), ResultState::Aborted);
+        assert_eq!(ResultState::from(9), ResultState::Unknown(9));
+    }
+
+    #[test]
+    fn sql_list() {
+        assert_eq!(ResultState::sql_list(&ResultState::FAILED), "3, 6, 7");
+        assert_eq!(ResultState::sql_list(&[]), "");
+    }
+
+    #[test]
+    fn xml_round_trip() {
+        #[derive(Deserialize, Serialize)]
+        #[serde(rename = "result")]
+        struct Result {
+            state: ResultState,
+        }
+
+        for (xml, state) in [
+            (
+                "<result><state>5</state></result>",
+                ResultState::FilesUploaded,
+            ),
+            (
+                "<result><state>12</state></result>",
+                ResultState::Unknown(12),
+            ),
+        ] {
+            let result: Result = quick_xml::de::from_str(xml).unwrap();
+            assert_eq!(result.state, state);
+            assert_eq!(quick_xml::se::to_string(&result).unwrap(), xml);
+        }
+    }
+
+    #[test]
+    fn sqlite_round_trip() {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch("CREATE TABLE result (state NUMBER)")
+            .unwrap();
+        for state in [ResultState::ComputeError, ResultState::Unknown(12)] {
+            conn.execute("DELETE FROM result", []).unwrap();
+            conn.execute("INSERT INTO result VALUES (?1)", [state])
+                .unwrap();
+            let raw: u32 = conn
+                .query_row("SELECT state FROM result", [], |row| row.get(0))
+                .unwrap();
+            assert_eq!(raw, u32::from(state));
+            let read: ResultState = conn
+                .query_row("SELECT state FROM result", [], |row| row.get(0))
+                .unwrap();
+            assert_eq!(read, state);
+        }
+    }
+}
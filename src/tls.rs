@@ -0,0 +1,88 @@
+use std::fs::File;
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+
+use anyhow::{bail, Context};
+use rustls::server::{ClientHello, ResolvesServerCert};
+use rustls::sign::{any_supported_type, CertifiedKey};
+use rustls::{Certificate, PrivateKey, ServerConfig};
+
+/// Serve a certificate chain and private key loaded from PEM files, that can be reloaded while the
+/// server is running
+pub struct CertificateResolver {
+    cert_path: PathBuf,
+    key_path: PathBuf,
+    current: RwLock<Arc<CertifiedKey>>,
+}
+
+impl CertificateResolver {
+    pub fn new(cert_path: PathBuf, key_path: PathBuf) -> anyhow::Result<Self> {
+        let certified_key = Self::load(&cert_path, &key_path)?;
+        Ok(CertificateResolver {
+            cert_path,
+            key_path,
+            current: RwLock::new(Arc::new(certified_key)),
+        })
+    }
+
+    fn load(cert_path: &Path, key_path: &Path) -> anyhow::Result<CertifiedKey> {
+        let certs = rustls_pemfile::certs(&mut BufReader::new(
+            File::open(cert_path)
+                .with_context(|| format!("Opening the certificate {}", cert_path.display()))?,
+        ))
+        .with_context(|| format!("Reading the certificate {}", cert_path.display()))?;
+        if certs.is_empty() {
+            bail!("No certificate found in {}", cert_path.display());
+        }
+
+        let mut key = None;
+        for item in rustls_pemfile::read_all(&mut BufReader::new(
+            File::open(key_path)
+                .with_context(|| format!("Opening the private key {}", key_path.display()))?,
+        ))
+        .with_context(|| format!("Reading the private key {}", key_path.display()))?
+        {
+            match item {
+                rustls_pemfile::Item::RSAKey(k)
+                | rustls_pemfile::Item::PKCS8Key(k)
+                | rustls_pemfile::Item::ECKey(k) => {
+                    key = Some(PrivateKey(k));
+                    break;
+                }
+                _ => (),
+            }
+        }
+        let key = match key {
+            Some(key) => key,
+            None => bail!("No private key found in {}", key_path.display()),
+        };
+        let signing_key = any_supported_type(&key)
+            .with_context(|| format!("Unsupported private key in {}", key_path.display()))?;
+
+        Ok(CertifiedKey::new(
+            certs.into_iter().map(Certificate).collect(),
+            signing_key,
+        ))
+    }
+
+    /// Read the files again. The current certificate is kept if they can’t be loaded.
+    pub fn reload(&self) -> anyhow::Result<()> {
+        let certified_key = Self::load(&self.cert_path, &self.key_path)?;
+        *self.current.write().unwrap() = Arc::new(certified_key);
+        Ok(())
+    }
+
+    pub fn server_config(self: Arc<Self>) -> ServerConfig {
+        ServerConfig::builder()
+            .with_safe_defaults()
+            .with_no_client_auth()
+            .with_cert_resolver(self)
+    }
+}
+
+impl ResolvesServerCert for CertificateResolver {
+    fn resolve(&self, _client_hello: ClientHello) -> Option<Arc<CertifiedKey>> {
+        Some(self.current.read().unwrap().clone())
+    }
+}